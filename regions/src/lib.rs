@@ -1,9 +1,10 @@
+use std::{fmt, str::FromStr, time::Duration};
 use strum_macros::EnumString;
 
 /// These are all derived from definitions in
 /// https://github.com/helium/miner/blob/master/config/sys.config
 
-#[derive(Debug, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, EnumString)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Region {
     US915,
@@ -39,6 +40,335 @@ impl Region {
             Region::RU864 => &RU864_UPLINK_FREQUENCIES,
         }
     }
+
+    /// Regions with a fixed channel plan split their 64 uplink channels into
+    /// 8 sub-bands. Helium only listens on one of them (see
+    /// get_uplink_frequencies) but 16 channel gateways cover two.
+    /// Returns an empty list for regions with a dynamic channel plan
+    pub fn get_sub_bands(&self) -> Vec<SubBand> {
+        let (first_channel, first_fat_channel) = match self {
+            Region::US915 => (902_300_000, 903_000_000),
+            Region::AU915 => (915_200_000, 915_900_000),
+            _ => return Vec::new(),
+        };
+        (0..8)
+            .map(|sub_band| {
+                let mut channels = [0; 8];
+                for (index, channel) in channels.iter_mut().enumerate() {
+                    *channel = first_channel + (sub_band * 8 + index) * 200_000;
+                }
+                SubBand {
+                    number: sub_band + 1,
+                    channels,
+                    fat_channel: first_fat_channel + sub_band * 1_600_000,
+                }
+            })
+            .collect()
+    }
+
+    /// Maximum MACPayload size (M in LoRaWAN Regional Parameters) for a data
+    /// rate given as eg: "SF9BW125". None if the region does not use it
+    pub fn get_max_payload_size(&self, datr: &str) -> Option<usize> {
+        let (spreading_factor, bandwidth) = match datr.parse().ok()? {
+            DataRate::Lora {
+                spreading_factor,
+                bandwidth,
+            } => (spreading_factor, bandwidth / 1000),
+            fsk => {
+                return if self.get_data_rates().contains(&fsk) {
+                    Some(250)
+                } else {
+                    None
+                }
+            }
+        };
+        match self {
+            Region::US915 => match (spreading_factor, bandwidth) {
+                (10, 125) => Some(19),
+                (9, 125) => Some(61),
+                (8, 125) => Some(133),
+                (7, 125) => Some(250),
+                (12, 500) => Some(61),
+                (11, 500) => Some(137),
+                (7..=10, 500) => Some(250),
+                _ => None,
+            },
+            Region::AU915 => match (spreading_factor, bandwidth) {
+                (10..=12, 125) => Some(59),
+                (9, 125) => Some(123),
+                (7..=8, 125) => Some(250),
+                (12, 500) => Some(61),
+                (11, 500) => Some(137),
+                (7..=10, 500) => Some(250),
+                _ => None,
+            },
+            _ => match (spreading_factor, bandwidth) {
+                (10..=12, 125) => Some(59),
+                (9, 125) => Some(123),
+                (7..=8, 125) => Some(250),
+                (7, 250) => Some(250),
+                _ => None,
+            },
+        }
+    }
+
+    /// Uplink data rates of the region, slowest first
+    pub fn get_data_rates(&self) -> Vec<DataRate> {
+        let lora = |spreading_factor, bandwidth| DataRate::Lora {
+            spreading_factor,
+            bandwidth,
+        };
+        let fsk = DataRate::Fsk { bitrate: 50_000 };
+        let (slowest, extra) = match self {
+            Region::US915 => (10, vec![lora(8, 500_000)]),
+            Region::AU915 => (12, vec![lora(8, 500_000)]),
+            Region::EU868
+            | Region::EU433
+            | Region::CN779
+            | Region::RU864
+            | Region::AS923_1
+            | Region::AS923_2
+            | Region::AS923_3
+            | Region::AS923_4 => (12, vec![lora(7, 250_000), fsk]),
+            Region::IN865 => (12, vec![fsk]),
+            Region::CN470 | Region::KR920 => (12, vec![]),
+        };
+        (7..=slowest)
+            .rev()
+            .map(|spreading_factor| lora(spreading_factor, 125_000))
+            .chain(extra)
+            .collect()
+    }
+
+    /// Frequencies on which a data rate is used: 125 kHz LoRa goes on the
+    /// multi-SF channels, 250 kHz LoRa on the LoRa standard channel, 500 kHz
+    /// LoRa on the fat channel of the sub-band and FSK on the FSK channel.
+    /// Empty when the region has no such channel
+    pub fn get_channels(&self, data_rate: &DataRate) -> Vec<usize> {
+        match data_rate {
+            DataRate::Lora {
+                bandwidth: 125_000, ..
+            } => self.get_multi_sf_frequencies().to_vec(),
+            DataRate::Lora {
+                bandwidth: 250_000, ..
+            } => self.get_lora_std_channel().into_iter().collect(),
+            DataRate::Lora {
+                bandwidth: 500_000, ..
+            } => self
+                .get_sub_bands()
+                .iter()
+                .find(|sub_band| {
+                    sub_band
+                        .channels
+                        .contains(&self.get_uplink_frequencies()[0])
+                })
+                .map(|sub_band| vec![sub_band.fat_channel])
+                .unwrap_or_default(),
+            DataRate::Fsk { .. } => self.get_fsk_channel().into_iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Uplink frequencies of the multi-SF channels, without the fat channel
+    /// listed last by EU868 and AU915
+    pub fn get_multi_sf_frequencies(&self) -> &[usize] {
+        let uplink = self.get_uplink_frequencies();
+        match self.get_lora_std_channel() {
+            Some(_) => &uplink[..uplink.len() - 1],
+            None => uplink,
+        }
+    }
+
+    /// Frequency of the LoRa standard (fat) channel listed with the uplink
+    /// frequencies: 250 kHz in EU868, 500 kHz in AU915
+    pub fn get_lora_std_channel(&self) -> Option<usize> {
+        match self {
+            Region::EU868 | Region::AU915 => self.get_uplink_frequencies().last().copied(),
+            _ => None,
+        }
+    }
+
+    /// Frequency of the FSK channel, in Hz. Only EU868 gives it a fixed one:
+    /// elsewhere FSK is a data rate gateways may not listen for at all
+    pub fn get_fsk_channel(&self) -> Option<usize> {
+        match self {
+            Region::EU868 => Some(868_800_000),
+            _ => None,
+        }
+    }
+
+    /// The Class B beacon sent at `beacon_time`, in GPS seconds. Regions
+    /// with several beacon channels hop between them every beacon period
+    pub fn get_beacon(&self, beacon_time: u32) -> Beacon {
+        let hop = (beacon_time / BEACON_PERIOD) as usize % 8;
+        let (frequency, spreading_factor, bandwidth, rfu) = match self {
+            Region::US915 | Region::AU915 => (923_300_000 + hop * 600_000, 12, 500_000, [5, 3]),
+            Region::CN470 => (508_300_000 + hop * 200_000, 10, 125_000, [3, 1]),
+            Region::IN865 => (866_550_000, 8, 125_000, [1, 3]),
+            Region::EU868 => (869_525_000, 9, 125_000, [2, 0]),
+            Region::EU433 => (434_665_000, 9, 125_000, [2, 0]),
+            Region::CN779 => (785_000_000, 9, 125_000, [2, 0]),
+            Region::RU864 => (869_100_000, 9, 125_000, [2, 0]),
+            Region::KR920 => (923_100_000, 9, 125_000, [2, 0]),
+            Region::AS923_1 => (923_400_000, 9, 125_000, [2, 0]),
+            Region::AS923_2 => (921_600_000, 9, 125_000, [2, 0]),
+            Region::AS923_3 => (916_800_000, 9, 125_000, [2, 0]),
+            Region::AS923_4 => (917_500_000, 9, 125_000, [2, 0]),
+        };
+        Beacon {
+            frequency,
+            data_rate: DataRate::Lora {
+                spreading_factor,
+                bandwidth,
+            },
+            rfu,
+        }
+    }
+}
+
+/// Seconds between two Class B beacons
+pub const BEACON_PERIOD: u32 = 128;
+
+/// Where and how a Class B beacon is sent. Its payload is
+/// `RFU | Time | CRC | GwSpecific | RFU | CRC`, with region specific RFU sizes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beacon {
+    /// in Hz
+    pub frequency: usize,
+    pub data_rate: DataRate,
+    /// sizes of the two RFU fields, in bytes
+    pub rfu: [usize; 2],
+}
+
+impl Beacon {
+    /// Preamble of a beacon, in symbols. It goes without a header or CRC
+    pub const PREAMBLE: usize = 10;
+
+    /// size of the payload, in bytes
+    pub fn size(&self) -> usize {
+        self.rfu[0] + 4 + 2 + 7 + self.rfu[1] + 2
+    }
+
+    pub fn time_on_air(&self) -> Duration {
+        self.data_rate
+            .frame_time_on_air(self.size(), Beacon::PREAMBLE, false, false)
+    }
+}
+
+/// Modulation and rate of a packet, as carried in the `datr` field of the
+/// Semtech UDP protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataRate {
+    /// bandwidth in Hz
+    Lora {
+        spreading_factor: u8,
+        bandwidth: usize,
+    },
+    /// bitrate in bits per second
+    Fsk { bitrate: usize },
+}
+
+impl DataRate {
+    pub fn bandwidth(&self) -> usize {
+        match self {
+            DataRate::Lora { bandwidth, .. } => *bandwidth,
+            // as configured for the FSK channel of the SX130x
+            DataRate::Fsk { .. } => 125_000,
+        }
+    }
+
+    /// Time a packet of `size` bytes spends on air, with an explicit header,
+    /// CRC, coding rate 4/5 and the default preamble of 8 symbols (5 bytes
+    /// for FSK)
+    pub fn time_on_air(&self, size: usize) -> Duration {
+        self.frame_time_on_air(size, 8, true, true)
+    }
+
+    /// Time on air of a LoRa frame of `size` bytes after `preamble` symbols,
+    /// at coding rate 4/5, with or without a header and CRC. FSK frames keep
+    /// their default preamble, and always have both
+    pub fn frame_time_on_air(
+        &self,
+        size: usize,
+        preamble: usize,
+        explicit_header: bool,
+        crc: bool,
+    ) -> Duration {
+        match *self {
+            DataRate::Lora {
+                spreading_factor,
+                bandwidth,
+            } => {
+                let sf = spreading_factor as i64;
+                let symbol = (1u64 << spreading_factor) as f64 / bandwidth as f64;
+                // low data rate optimization kicks in above 16 ms per symbol
+                let de = if symbol > 0.016 { 1 } else { 0 };
+                let header = if explicit_header { 0 } else { 20 };
+                let crc = if crc { 16 } else { 0 };
+                let bits = 8 * size as i64 - 4 * sf + 28 + crc - header;
+                let blocks = (bits.max(0) as f64 / (4 * (sf - 2 * de)) as f64).ceil();
+                let symbols = preamble as f64 + 4.25 + 8.0 + blocks * 5.0;
+                Duration::from_secs_f64(symbols * symbol)
+            }
+            DataRate::Fsk { bitrate } => {
+                // preamble, sync word, length, payload and CRC
+                let bytes = 5 + 3 + 1 + size + 2;
+                Duration::from_secs_f64((bytes * 8) as f64 / bitrate as f64)
+            }
+        }
+    }
+}
+
+impl fmt::Display for DataRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataRate::Lora {
+                spreading_factor,
+                bandwidth,
+            } => write!(f, "SF{}BW{}", spreading_factor, bandwidth / 1000),
+            DataRate::Fsk { bitrate } => write!(f, "FSK{}", bitrate),
+        }
+    }
+}
+
+/// Accepts "SF7BW125" for LoRa and "FSK50000" or a bare bitrate for FSK
+impl FromStr for DataRate {
+    type Err = String;
+
+    fn from_str(datr: &str) -> Result<DataRate, String> {
+        let invalid = || format!("invalid data rate: {}", datr);
+        if let Some(lora) = datr.strip_prefix("SF") {
+            let mut parts = lora.split("BW");
+            let spreading_factor = parts
+                .next()
+                .and_then(|sf| sf.parse().ok())
+                .ok_or_else(invalid)?;
+            let bandwidth: usize = parts
+                .next()
+                .and_then(|bw| bw.parse().ok())
+                .ok_or_else(invalid)?;
+            Ok(DataRate::Lora {
+                spreading_factor,
+                bandwidth: bandwidth * 1000,
+            })
+        } else {
+            let bitrate = datr
+                .strip_prefix("FSK")
+                .unwrap_or(datr)
+                .parse()
+                .map_err(|_| invalid())?;
+            Ok(DataRate::Fsk { bitrate })
+        }
+    }
+}
+
+/// Eight 125 kHz channels and the 500 kHz channel that sits on top of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubBand {
+    /// 1-based, as used in LoRaWAN Regional Parameters
+    pub number: usize,
+    pub channels: [usize; 8],
+    pub fat_channel: usize,
 }
 
 pub const US915_UPLINK_FREQUENCIES: [usize; 8] = [
@@ -0,0 +1,50 @@
+use regions::{DataRate, Region};
+
+fn lora(spreading_factor: u8, bandwidth: usize) -> DataRate {
+    DataRate::Lora {
+        spreading_factor,
+        bandwidth,
+    }
+}
+
+#[test]
+fn eu868_channels() {
+    let region = Region::EU868;
+    let multi_sf = region.get_channels(&lora(12, 125_000));
+    assert_eq!(multi_sf.len(), 8);
+    assert_eq!(multi_sf, &region.get_uplink_frequencies()[..8]);
+    assert_eq!(region.get_channels(&lora(7, 250_000)), [868_300_000]);
+    assert_eq!(
+        region.get_channels(&DataRate::Fsk { bitrate: 50_000 }),
+        [868_800_000]
+    );
+}
+
+#[test]
+fn au915_channels() {
+    let region = Region::AU915;
+    let multi_sf = region.get_channels(&lora(10, 125_000));
+    assert_eq!(multi_sf.len(), 8);
+    assert!(!multi_sf.contains(&917_500_000));
+    assert_eq!(region.get_channels(&lora(8, 500_000)), [917_500_000]);
+}
+
+#[test]
+fn us915_channels() {
+    let region = Region::US915;
+    assert_eq!(
+        region.get_channels(&lora(7, 125_000)),
+        region.get_uplink_frequencies()
+    );
+    assert_eq!(region.get_channels(&lora(8, 500_000)), [904_600_000]);
+}
+
+#[test]
+fn as923_has_no_fat_or_fsk_channel() {
+    let region = Region::AS923_1;
+    assert_eq!(region.get_channels(&lora(7, 125_000)).len(), 8);
+    assert!(region.get_channels(&lora(7, 250_000)).is_empty());
+    assert!(region
+        .get_channels(&DataRate::Fsk { bitrate: 50_000 })
+        .is_empty());
+}
@@ -0,0 +1,82 @@
+use rf_tester::simulator::{Gateway, Medium};
+use std::net::SocketAddr;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rf-simulator",
+    about = "Simulated test and control gateways for rf-tester"
+)]
+struct Opt {
+    /// rf-tester ports the tested gateways connect to, one gateway each
+    #[structopt(long = "test-port", default_value = "1680")]
+    test_ports: Vec<u16>,
+
+    /// rf-tester ports the control gateways connect to, one gateway each
+    #[structopt(long = "control-port", default_value = "1681")]
+    control_ports: Vec<u16>,
+
+    /// probability, from 0 to 1, that a packet is lost
+    #[structopt(long, default_value = "0")]
+    loss: f64,
+
+    /// RSSI of every packet sent at 12 dBm, in dBm. It follows the transmit
+    /// power dB for dB
+    #[structopt(long, default_value = "-60", allow_hyphen_values = true)]
+    rssi: i32,
+
+    /// SNR of every packet received, in dB
+    #[structopt(long, default_value = "9.5", allow_hyphen_values = true)]
+    snr: f32,
+
+    /// how far from its channel every packet received is, in Hz. Reported
+    /// as foff up to 100 kHz, as a shifted frequency beyond
+    #[structopt(long, default_value = "0", allow_hyphen_values = true)]
+    frequency_error: i64,
+
+    /// simulate gateways which do not report foff, as SX1301 ones
+    #[structopt(long)]
+    no_foff: bool,
+
+    /// seed of the packet loss, so that a run can be reproduced
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// simulate gateways without a GPS fix
+    #[structopt(long)]
+    no_gps: bool,
+
+    /// how often the gateways send a stat report, in s. 0 for never
+    #[structopt(long, default_value = "30")]
+    stat_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Opt::from_args();
+    let medium = Medium {
+        loss: cli.loss,
+        rssi: cli.rssi,
+        snr: cli.snr,
+        frequency_error: cli.frequency_error,
+        foff: !cli.no_foff,
+        seed: cli.seed,
+        gps: !cli.no_gps,
+        stat_interval: cli.stat_interval,
+    };
+    // tested gateways get odd EUIs and control gateways even ones
+    let tested = cli.test_ports.iter().enumerate().map(|(index, port)| {
+        Gateway::new(0xAA55_5A00_0000_0001 + 2 * index as u64, localhost(*port))
+    });
+    let control = cli.control_ports.iter().enumerate().map(|(index, port)| {
+        Gateway::new(0xAA55_5A00_0000_0002 + 2 * index as u64, localhost(*port))
+    });
+    medium.spawn(tested.chain(control).collect()).await?;
+    println!("Simulated gateways running, press Ctrl-C to stop");
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+fn localhost(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
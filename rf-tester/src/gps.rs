@@ -0,0 +1,48 @@
+//! GPS time, as used by `tmms` and Class B beacons
+use regions::Beacon;
+
+/// 1980-01-06T00:00:00Z, the start of GPS time
+pub const GPS_EPOCH_UNIX_MS: u64 = 315_964_800_000;
+
+/// GPS time runs ahead of UTC by the leap seconds inserted since 1980
+pub const LEAP_SECONDS: u64 = 18;
+
+pub fn gps_ms(unix_ms: u64) -> u64 {
+    unix_ms - GPS_EPOCH_UNIX_MS + LEAP_SECONDS * 1000
+}
+
+pub fn unix_ms(gps_ms: u64) -> u64 {
+    gps_ms + GPS_EPOCH_UNIX_MS - LEAP_SECONDS * 1000
+}
+
+/// Payload of the beacon sent at `time`, in GPS seconds. The gateway
+/// specific part is left as InfoDesc 0 (antenna coordinates) at 0°, 0°
+pub fn beacon_payload(beacon: &Beacon, time: u32) -> Vec<u8> {
+    let [rfu1, rfu2] = beacon.rfu;
+    let mut payload = vec![0; rfu1];
+    payload.extend_from_slice(&time.to_le_bytes());
+    payload.extend_from_slice(&crc16(&payload).to_le_bytes());
+
+    let start = payload.len();
+    payload.extend_from_slice(&[0; 7]);
+    payload.extend_from_slice(&vec![0; rfu2]);
+    let crc = crc16(&payload[start..]);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+// CRC-16/XMODEM, as used by the beacon: polynomial 0x1021, initial value 0
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,257 @@
+//! The `stat` reports packet forwarders send in their PUSH_DATA, and how
+//! their counters compare with the downlinks rf-tester sent. Also how well
+//! they keep their connection up, by their PULL_DATA
+use super::report::{mac_to_string, now_ms};
+use semtech_udp::{push_data, MacAddress};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Mutex, time::Duration};
+
+/// A `stat` report. Packet forwarders count over the interval since their
+/// previous report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayStat {
+    /// system time of the gateway
+    pub time: String,
+    pub lati: Option<f64>,
+    pub long: Option<f64>,
+    /// in m
+    pub alti: Option<i64>,
+    /// radio packets received
+    pub rxnb: u64,
+    /// radio packets received with a valid CRC
+    pub rxok: u64,
+    /// radio packets forwarded
+    pub rxfw: u64,
+    /// percentage of upstream datagrams that were acknowledged
+    pub ackr: f64,
+    /// downlink datagrams received
+    pub dwnb: u64,
+    /// packets emitted
+    pub txnb: u64,
+}
+
+impl GatewayStat {
+    /// semtech-udp keeps the fields of its `Stat` to itself
+    pub fn from_push_data(stat: &push_data::Stat) -> Option<GatewayStat> {
+        serde_json::to_value(stat)
+            .and_then(serde_json::from_value)
+            .ok()
+    }
+}
+
+impl fmt::Display for GatewayStat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rxnb {}, rxok {}, rxfw {}, ackr {:.1}%, dwnb {}, txnb {}",
+            self.rxnb, self.rxok, self.rxfw, self.ackr, self.dwnb, self.txnb
+        )?;
+        if let (Some(lati), Some(long)) = (self.lati, self.long) {
+            write!(f, ", at {:.5}, {:.5}", lati, long)?;
+            if let Some(alti) = self.alti {
+                write!(f, ", {} m", alti)?;
+            }
+        }
+        write!(f, ", time {}", self.time)
+    }
+}
+
+// what a gateway reported and what it was sent over the run
+#[derive(Debug, Default)]
+struct Tally {
+    /// with the unix time in ms they were received at
+    stats: Vec<(u64, GatewayStat)>,
+    downlinks: u64,
+    transmitted: u64,
+    /// unix time in ms of the latest PULL_DATA
+    last_keepalive: Option<u64>,
+    /// whether the current silence was already counted as a gap
+    silent: bool,
+    // since health was last taken
+    keepalives: u64,
+    longest_gap: Option<u64>,
+    gaps: u64,
+    reconnects: u64,
+}
+
+/// Collects the `stat` reports and the PULL_DATA of every gateway, and the
+/// downlinks sent to them, as the servers and the test go
+#[derive(Debug)]
+pub struct Monitor {
+    tallies: Mutex<HashMap<String, Tally>>,
+    /// longest silence between two PULL_DATA not counted as a gap, in ms
+    max_keepalive_gap: u64,
+}
+
+impl Monitor {
+    pub fn new(max_keepalive_gap: Duration) -> Monitor {
+        Monitor {
+            tallies: Mutex::new(HashMap::new()),
+            max_keepalive_gap: max_keepalive_gap.as_millis() as u64,
+        }
+    }
+
+    fn tally<T>(&self, mac: &MacAddress, f: impl FnOnce(&mut Tally) -> T) -> T {
+        let mut tallies = self.tallies.lock().expect("no one panics holding the lock");
+        f(tallies.entry(mac_to_string(mac)).or_default())
+    }
+
+    pub fn record_stat(&self, mac: &MacAddress, stat: GatewayStat) {
+        println!("\tStat from {}: {}", mac_to_string(mac), stat);
+        self.tally(mac, |tally| tally.stats.push((now_ms(), stat)));
+    }
+
+    /// Counts a PULL_RESP sent to the gateway, and whether it was
+    /// acknowledged without error
+    pub fn record_downlink(&self, mac: &MacAddress, transmitted: bool) {
+        self.tally(mac, |tally| {
+            tally.downlinks += 1;
+            if transmitted {
+                tally.transmitted += 1;
+            }
+        });
+    }
+
+    pub fn record_keepalive(&self, mac: &MacAddress) {
+        let now = now_ms();
+        let max_gap = self.max_keepalive_gap;
+        self.tally(mac, |tally| {
+            if let Some(last) = tally.last_keepalive.replace(now) {
+                let gap = now.saturating_sub(last);
+                tally.longest_gap = Some(tally.longest_gap.map_or(gap, |longest| longest.max(gap)));
+                if gap > max_gap && !tally.silent {
+                    tally.gaps += 1;
+                }
+            }
+            tally.silent = false;
+            tally.keepalives += 1;
+        });
+    }
+
+    /// Counts a gateway coming back from another address, as it does when
+    /// its packet forwarder restarts
+    pub fn record_reconnect(&self, mac: &MacAddress) {
+        self.tally(mac, |tally| tally.reconnects += 1);
+    }
+
+    /// How the gateway kept its connection up since this was last called
+    pub fn take_health(&self, mac: &MacAddress) -> GatewayHealth {
+        let now = now_ms();
+        let max_gap = self.max_keepalive_gap;
+        self.tally(mac, |tally| {
+            // a gateway which went quiet has no PULL_DATA to count it by
+            if let Some(last) = tally.last_keepalive {
+                let gap = now.saturating_sub(last);
+                if gap > max_gap {
+                    tally.longest_gap =
+                        Some(tally.longest_gap.map_or(gap, |longest| longest.max(gap)));
+                    if !tally.silent {
+                        tally.gaps += 1;
+                        tally.silent = true;
+                    }
+                }
+            }
+            let health = GatewayHealth {
+                mac: mac_to_string(mac),
+                keepalives: tally.keepalives,
+                longest_gap: tally.longest_gap,
+                gaps: tally.gaps,
+                reconnects: tally.reconnects,
+                // still quiet at the end of the period
+                passed: tally.gaps == 0 && tally.reconnects == 0 && !tally.silent,
+            };
+            tally.keepalives = 0;
+            tally.longest_gap = None;
+            tally.gaps = 0;
+            tally.reconnects = 0;
+            health
+        })
+    }
+
+    /// Unix time in ms of the gateway's latest report
+    pub fn last_stat_at(&self, mac: &MacAddress) -> Option<u64> {
+        self.tally(mac, |tally| tally.stats.last().map(|(at, _)| *at))
+    }
+
+    pub fn check(&self, mac: &MacAddress) -> StatCheck {
+        self.tally(mac, |tally| {
+            let dwnb = tally.stats.iter().map(|(_, stat)| stat.dwnb).sum();
+            let txnb = tally.stats.iter().map(|(_, stat)| stat.txnb).sum();
+            StatCheck {
+                mac: mac_to_string(mac),
+                reports: tally.stats.len(),
+                dwnb,
+                txnb,
+                downlinks: tally.downlinks,
+                transmitted: tally.transmitted,
+                passed: !tally.stats.is_empty()
+                    && dwnb == tally.downlinks
+                    && txnb == tally.transmitted,
+            }
+        })
+    }
+}
+
+/// How the counters a gateway reported over the run compare with the
+/// downlinks it was sent
+#[derive(Debug, Serialize)]
+pub struct StatCheck {
+    pub mac: String,
+    /// `stat` reports received
+    pub reports: usize,
+    /// sum of the reported downlink datagrams
+    pub dwnb: u64,
+    /// sum of the reported packets emitted
+    pub txnb: u64,
+    /// PULL_RESP sent to the gateway
+    pub downlinks: u64,
+    /// PULL_RESP acknowledged without error
+    pub transmitted: u64,
+    pub passed: bool,
+}
+
+impl StatCheck {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}: {} stat reports, dwnb {} for {} downlinks, txnb {} for {} transmitted",
+            if self.passed { "PASS" } else { "FAIL" },
+            self.mac,
+            self.reports,
+            self.dwnb,
+            self.downlinks,
+            self.txnb,
+            self.transmitted
+        )
+    }
+}
+
+/// How a gateway kept its connection to rf-tester up over a while
+#[derive(Debug, Serialize)]
+pub struct GatewayHealth {
+    pub mac: String,
+    /// PULL_DATA received
+    pub keepalives: u64,
+    /// longest time without PULL_DATA, in ms
+    pub longest_gap: Option<u64>,
+    /// times PULL_DATA stopped for longer than allowed
+    pub gaps: u64,
+    /// times the gateway came back from another address
+    pub reconnects: u64,
+    pub passed: bool,
+}
+
+impl GatewayHealth {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}: {} keepalives, longest gap {} s, {} gaps, {} reconnects",
+            if self.passed { "PASS" } else { "FAIL" },
+            self.mac,
+            self.keepalives,
+            self.longest_gap
+                .map(|gap| format!("{:.1}", gap as f64 / 1000.0))
+                .unwrap_or_else(|| "-".to_string()),
+            self.gaps,
+            self.reconnects
+        )
+    }
+}
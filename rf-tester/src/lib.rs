@@ -0,0 +1,25 @@
+//! Tests the RF of a LoRa gateway against a known good one.
+//!
+//! Both gateways run a Semtech UDP packet forwarder pointed at rf-tester,
+//! which has each of them transmit on the region's uplink channels and
+//! checks that the other one receives the packets. The [`simulator`] stands
+//! in for the gateways when there is no hardware around.
+
+pub mod gps;
+pub mod health;
+pub mod opt;
+pub mod payload;
+pub mod power_sweep;
+pub mod record;
+pub mod replay;
+pub mod report;
+pub mod simulator;
+pub mod soak;
+pub mod tester;
+pub mod tx_error;
+pub mod udp;
+pub mod wire;
+
+pub use opt::Opt;
+pub use report::{Report, Verdict};
+pub use tester::run;
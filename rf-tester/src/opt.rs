@@ -0,0 +1,465 @@
+use super::{
+    payload,
+    report::{ReportFormat, Thresholds},
+    tester::{Role, RxWindow, Step},
+    tx_error::TxPolicy,
+};
+use regions::{DataRate, Region};
+use semtech_udp::MacAddress;
+use std::{path::PathBuf, time::Duration};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "semtech-server", about = "LoRa test device utility")]
+pub struct Opt {
+    /// Ports the gateways under test connect to, one gateway per port.
+    /// Several ports test a batch of gateways, one after the other. Testing
+    /// them in parallel, each on its own frequencies, is not supported yet
+    #[structopt(long = "test-port", default_value = "1680")]
+    pub test_ports: Vec<u16>,
+
+    /// Ports the control gateways connect to, one gateway per port. Every
+    /// gateway under test is tested against each of them
+    #[structopt(long = "control-port", default_value = "1681")]
+    pub control_ports: Vec<u16>,
+
+    /// EUIs of the gateways under test, eg: --test-mac AA555A0000000001.
+    /// Only these are let in, on any port, and each is tested in turn.
+    /// Without it, the first gateway to connect to each --test-port is
+    /// tested
+    #[structopt(long, parse(try_from_str = parse_eui))]
+    pub test_mac: Vec<u64>,
+
+    /// EUIs of the control gateways. With --test-mac, both roles can share
+    /// a port, eg: --test-port 1680 --control-port 1680
+    #[structopt(long, parse(try_from_str = parse_eui))]
+    pub control_mac: Vec<u64>,
+
+    /// which region to use for the RF test (eg: EU868, US915...)
+    #[structopt(long, short)]
+    pub region: Region,
+
+    /// output all UDP frames received from both control and test gateways
+    #[structopt(long, short)]
+    pub debug: bool,
+
+    /// transmit power. allowable range, 12-28
+    #[structopt(long, default_value = "12")]
+    pub power: u64,
+
+    /// data rate
+    #[structopt(long, default_value = "SF12BW125")]
+    pub datr: String,
+
+    /// sweep the transmit power of the test gateway from START to END dBm
+    /// on the first uplink channel and check that the control gateway's RSSI
+    /// follows, eg: --power-sweep 12 28
+    #[structopt(long, number_of_values = 2, value_names = &["start", "end"])]
+    pub power_sweep: Vec<u64>,
+
+    /// increment of the power sweep, in dB. The end of the sweep is always
+    /// one of its steps
+    #[structopt(long, default_value = "2")]
+    pub power_step: u64,
+
+    /// largest deviation from a 1 dB/dB RSSI response accepted during the
+    /// power sweep, in dB
+    #[structopt(long, default_value = "2")]
+    pub max_power_deviation: f64,
+
+    /// largest distance of the power sweep's fitted RSSI slope from
+    /// 1 dB/dB accepted, in dB/dB
+    #[structopt(long, default_value = "0.2")]
+    pub max_power_slope_error: f64,
+
+    /// payload size in bytes, from 14 up to the region's maximum for the
+    /// data rate
+    #[structopt(long, default_value = "52")]
+    pub size: usize,
+
+    /// test every data rate of the region instead of --datr, each on the
+    /// channels it is used on: 125 kHz on the multi-SF channels, 250 and
+    /// 500 kHz on the fat channel. Data rates without a channel in the region
+    /// are left out. --size is capped to what each data rate allows
+    #[structopt(long)]
+    pub sweep: bool,
+
+    /// also test the region's FSK channel, at 50 kbps. --size is capped to
+    /// what FSK allows
+    #[structopt(long)]
+    pub fsk: bool,
+
+    /// frequency deviation of FSK packets, in Hz
+    #[structopt(long, default_value = "25000")]
+    pub fdev: u64,
+
+    /// number of packets to send on each channel
+    #[structopt(long, default_value = "1")]
+    pub count: usize,
+
+    /// delay between packets on the same channel, in ms. Should exceed the
+    /// time on air of the data rate
+    #[structopt(long, default_value = "3000")]
+    pub interval: u64,
+
+    /// how long to wait for the last packet of a channel, in ms
+    #[structopt(long, default_value = "10000")]
+    pub timeout: u64,
+
+    /// also have the test gateway answer packets in the RX1 or RX2 window,
+    /// scheduled against its concentrator counter, eg: --schedule rx1
+    #[structopt(long)]
+    pub schedule: Option<RxWindow>,
+
+    /// also have the test gateway transmit at GPS times (tmms), to check
+    /// its PPS and GPS path. The host clock must be in sync
+    #[structopt(long)]
+    pub gps: bool,
+
+    /// also have the test gateway send a Class B beacon in the next beacon
+    /// slot, up to 128 s away. The control gateway must listen on the
+    /// region's beacon channel
+    #[structopt(long)]
+    pub beacon: bool,
+
+    /// moves the beacon slots this many ms past the GPS times which are
+    /// multiples of 128 s. Not an option: only tests, which cannot wait for
+    /// a real slot, set it
+    #[structopt(skip)]
+    pub beacon_offset: u64,
+
+    /// also send packets with inverted IQ, as downlinks are, both ways on
+    /// the first uplink channel and check that neither gateway hears them,
+    /// while it does hear the same packets sent with normal IQ just before.
+    /// The reverse, a receiver with inverted IQ missing normal packets, is
+    /// out of scope: the packet forwarder cannot invert the IQ it listens
+    /// with
+    #[structopt(long)]
+    pub inverted_iq: bool,
+
+    /// also have the test gateway send packets without a CRC on the first
+    /// uplink channel and check that the control gateway reports them as
+    /// such. The control gateway must forward them (forward_crc_disabled)
+    #[structopt(long)]
+    pub no_crc: bool,
+
+    /// largest accepted error on the timing of scheduled transmissions,
+    /// in ms
+    #[structopt(long, default_value = "50")]
+    pub schedule_tolerance: u64,
+
+    /// what to do when a gateway does not transmit a packet: retry it on
+    /// transient errors (retry), give up on the channel (skip) or go on with
+    /// the next packet (continue)
+    #[structopt(long, default_value = "retry")]
+    pub on_tx_error: TxPolicy,
+
+    /// times a packet is sent again after a transient TX error
+    #[structopt(long, default_value = "2")]
+    pub tx_retries: usize,
+
+    /// highest packet error rate (0 to 1) for a channel to pass
+    #[structopt(long, default_value = "0")]
+    pub max_per: f64,
+
+    /// largest mean frequency error for a channel to pass, in ppm. Covers
+    /// the oscillators of both gateways, as measured from the frequency the
+    /// receiver reports and its demodulator's offset (foff). Receivers which
+    /// do not report foff leave it unmeasured
+    #[structopt(long, default_value = "10")]
+    pub max_ppm: f64,
+
+    /// lowest RSSI, in dBm, of any packet for a channel to pass
+    #[structopt(long, allow_hyphen_values = true)]
+    pub min_rssi: Option<f64>,
+
+    /// lowest SNR, in dB, of any packet for a LoRa channel to pass
+    #[structopt(long, allow_hyphen_values = true)]
+    pub min_snr: Option<f64>,
+
+    /// RSSI range, in dBm, every packet sent at --power must be received in,
+    /// as the attenuation of a conducted or chambered setup gives. Follows
+    /// the power during a power sweep, eg: --rssi-window -75 -65
+    #[structopt(
+        long,
+        number_of_values = 2,
+        value_names = &["min", "max"],
+        allow_hyphen_values = true
+    )]
+    pub rssi_window: Vec<f64>,
+
+    /// at the end of the run, wait for a stat report from every gateway and
+    /// check that their downlink (dwnb) and transmit (txnb) counters add up
+    /// to the downlinks sent. Gateways must report stats to rf-tester only
+    #[structopt(long)]
+    pub check_stats: bool,
+
+    /// stat interval of the packet forwarders, in s
+    #[structopt(long, default_value = "30")]
+    pub stat_interval: u64,
+
+    /// instead of testing once, repeat the TX and RX directions for this
+    /// long, eg: --soak 8h. Other tests are left out
+    #[structopt(long, parse(try_from_str = parse_duration))]
+    pub soak: Option<Duration>,
+
+    /// length of the periods a soak run is summed up over, eg: 30m
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration))]
+    pub soak_period: Duration,
+
+    /// append the summary of every soak period to a file as soon as it
+    /// ends, one JSON object per line
+    #[structopt(long, parse(from_os_str))]
+    pub soak_report: Option<PathBuf>,
+
+    /// longest time without PULL_DATA from a gateway before a soak run
+    /// counts a gap, in s
+    #[structopt(long, default_value = "30")]
+    pub max_keepalive_gap: u64,
+
+    /// record every frame to and from the gateways in a file, which
+    /// `rf-tester replay` reads back
+    #[structopt(long, parse(from_os_str))]
+    pub record: Option<PathBuf>,
+
+    /// write a report of the results, eg: --report junit results.xml.
+    /// Formats are json or junit
+    #[structopt(long, number_of_values = 2, value_names = &["format", "path"])]
+    pub report: Vec<String>,
+}
+
+impl Opt {
+    /// The data rates to test and the channels to test each of them on
+    pub(crate) fn steps(&self) -> Vec<Step> {
+        let step = |data_rate: DataRate| Step {
+            datr: data_rate.to_string(),
+            power: self.power,
+            channels: self.region.get_channels(&data_rate),
+            ipol: false,
+            crc: true,
+        };
+        if self.sweep {
+            return self
+                .region
+                .get_data_rates()
+                .into_iter()
+                .map(step)
+                .filter(|step| !step.channels.is_empty())
+                .collect();
+        }
+        let mut steps = vec![Step {
+            datr: self.datr.clone(),
+            power: self.power,
+            channels: self.region.get_uplink_frequencies().to_vec(),
+            ipol: false,
+            crc: true,
+        }];
+        if self.fsk {
+            steps.extend(self.fsk_data_rate().map(step));
+        }
+        steps
+    }
+
+    fn fsk_data_rate(&self) -> Option<DataRate> {
+        self.region
+            .get_data_rates()
+            .into_iter()
+            .find(|data_rate| matches!(data_rate, DataRate::Fsk { .. }))
+    }
+
+    pub(crate) fn check_fsk(&self) -> Result<(), String> {
+        let channels = self
+            .fsk_data_rate()
+            .map(|data_rate| self.region.get_channels(&data_rate))
+            .unwrap_or_default();
+        if self.fsk && channels.is_empty() {
+            return Err(format!("{:?} has no FSK channel to test", self.region));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn power_steps(&self) -> Result<Vec<Step>, String> {
+        let (start, end) = match self.power_sweep.as_slice() {
+            [] => return Ok(Vec::new()),
+            [start, end] if start < end && self.power_step > 0 => (*start, *end),
+            _ => {
+                return Err(
+                    "--power-sweep expects a start below the end and a non-zero --power-step"
+                        .to_string(),
+                )
+            }
+        };
+        // the end is always swept, even when the step overshoots it
+        let mut powers: Vec<u64> = (start..end).step_by(self.power_step as usize).collect();
+        powers.push(end);
+        Ok(powers
+            .into_iter()
+            .map(|power| self.first_channel_step(power, false, true))
+            .collect())
+    }
+
+    /// --datr at `power` on the first uplink channel only
+    pub(crate) fn first_channel_step(&self, power: u64, ipol: bool, crc: bool) -> Step {
+        Step {
+            datr: self.datr.clone(),
+            power,
+            channels: vec![self.region.get_uplink_frequencies()[0]],
+            ipol,
+            crc,
+        }
+    }
+
+    pub(crate) fn size_for(&self, datr: &str) -> usize {
+        if self.sweep || datr != self.datr {
+            self.region
+                .get_max_payload_size(datr)
+                .map_or(self.size, |max| self.size.min(max))
+        } else {
+            self.size
+        }
+    }
+
+    pub(crate) fn check_size(&self) -> Result<(), String> {
+        let max = match self.region.get_max_payload_size(&self.datr) {
+            // capped per data rate, see size_for
+            _ if self.sweep => payload::MAX_SIZE,
+            Some(max) => max,
+            None => {
+                println!(
+                    "No payload limit known for {} in {:?}, allowing up to {} bytes",
+                    self.datr,
+                    self.region,
+                    payload::MAX_SIZE
+                );
+                payload::MAX_SIZE
+            }
+        };
+        if self.size < payload::MIN_SIZE || self.size > max {
+            return Err(format!(
+                "--size must be between {} and {} bytes for {} in {:?}",
+                payload::MIN_SIZE,
+                max,
+                self.datr,
+                self.region
+            ));
+        }
+        Ok(())
+    }
+
+    /// What a channel tested at `power` must achieve to pass
+    pub fn thresholds(&self, power: u64) -> Thresholds {
+        let shift = power as f64 - self.power as f64;
+        Thresholds {
+            max_per: self.max_per,
+            max_ppm: self.max_ppm,
+            min_rssi: self.min_rssi,
+            min_snr: self.min_snr,
+            rssi_window: match self.rssi_window.as_slice() {
+                [min, max] => Some((min + shift, max + shift)),
+                _ => None,
+            },
+        }
+    }
+
+    pub(crate) fn check_thresholds(&self) -> Result<(), String> {
+        match self.rssi_window.as_slice() {
+            [] => Ok(()),
+            [min, max] if min <= max => Ok(()),
+            _ => Err("--rssi-window expects a minimum below the maximum".to_string()),
+        }
+    }
+
+    /// Every port to listen on, once each
+    pub(crate) fn ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = Vec::new();
+        for port in self.test_ports.iter().chain(&self.control_ports) {
+            if !ports.contains(port) {
+                ports.push(*port);
+            }
+        }
+        ports
+    }
+
+    pub(crate) fn check_gateways(&self) -> Result<(), String> {
+        let shared = self
+            .test_ports
+            .iter()
+            .any(|port| self.control_ports.contains(port));
+        if shared && (self.test_mac.is_empty() || self.control_mac.is_empty()) {
+            return Err(
+                "--test-port and --control-port can only share a port with --test-mac and --control-mac"
+                    .to_string(),
+            );
+        }
+        if let Some(mac) = self
+            .test_mac
+            .iter()
+            .find(|mac| self.control_mac.contains(mac))
+        {
+            return Err(format!(
+                "{:016X} cannot be both under test and a control gateway",
+                mac
+            ));
+        }
+        Ok(())
+    }
+
+    /// How many gateways under test and control gateways to wait for
+    pub(crate) fn gateway_counts(&self) -> (usize, usize) {
+        let count = |macs: &[u64], ports: &[u16]| {
+            if macs.is_empty() {
+                ports.len()
+            } else {
+                macs.len()
+            }
+        };
+        (
+            count(&self.test_mac, &self.test_ports),
+            count(&self.control_mac, &self.control_ports),
+        )
+    }
+
+    /// Where a gateway of `role` that connected to `port` was given on the
+    /// command line: by its EUI, or else by its port
+    pub(crate) fn gateway_order(&self, role: Role, mac: &MacAddress, port: u16) -> usize {
+        let (macs, ports) = match role {
+            Role::Tested => (&self.test_mac, &self.test_ports),
+            Role::Control => (&self.control_mac, &self.control_ports),
+        };
+        macs.iter()
+            .position(|listed| MacAddress::new(&listed.to_be_bytes()) == *mac)
+            .or_else(|| ports.iter().position(|listed| *listed == port))
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn report(&self) -> Result<Option<(ReportFormat, PathBuf)>, String> {
+        match self.report.as_slice() {
+            [] => Ok(None),
+            [format, path] => Ok(Some((format.parse()?, PathBuf::from(path)))),
+            _ => Err("--report expects a format and a path".to_string()),
+        }
+    }
+}
+
+/// A number of seconds, or of minutes or hours with an m or h suffix
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.chars().last() {
+        Some('h') => (&s[..s.len() - 1], 3600),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('s') => (&s[..s.len() - 1], 1),
+        _ => (s, 1),
+    };
+    number
+        .parse::<u64>()
+        .map(|number| Duration::from_secs(number * unit))
+        .map_err(|_| format!("{} is not a duration, eg: 90s, 30m or 8h", s))
+}
+
+/// A gateway EUI as 16 hex digits, optionally split by colons or dashes
+pub(crate) fn parse_eui(s: &str) -> Result<u64, String> {
+    let digits: String = s.chars().filter(|c| *c != ':' && *c != '-').collect();
+    if digits.len() != 16 {
+        return Err(format!("{} is not a 64-bit EUI", s));
+    }
+    u64::from_str_radix(&digits, 16).map_err(|_| format!("{} is not a 64-bit EUI", s))
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+
+// nonce (4), sequence (4), channel index (2) ... crc32 (4)
+const HEADER_SIZE: usize = 10;
+const CHECKSUM_SIZE: usize = 4;
+pub const MIN_SIZE: usize = HEADER_SIZE + CHECKSUM_SIZE;
+/// Largest PHY payload a LoRa frame can carry
+pub const MAX_SIZE: usize = 255;
+
+/// What rf-tester embeds in every packet so that a received frame can be
+/// traced back to the transmission that produced it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Payload {
+    /// random per run, tells our frames apart from anything else on air
+    pub nonce: u32,
+    pub sequence: u32,
+    pub channel: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// too short to be one of ours
+    TooShort,
+    /// the header could be read but the checksum does not match
+    Checksum(Payload),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooShort => write!(f, "payload too short"),
+            Error::Checksum(payload) => write!(
+                f,
+                "checksum mismatch on packet #{} for channel {}",
+                payload.sequence,
+                payload.channel as usize + 1
+            ),
+        }
+    }
+}
+
+impl Payload {
+    /// Serializes to `size` bytes, which must be at least [`MIN_SIZE`]. The
+    /// space between header and checksum is filled with a pattern derived
+    /// from the header so that corruption anywhere in the frame is caught
+    pub fn encode(&self, size: usize) -> Vec<u8> {
+        assert!(
+            size >= MIN_SIZE,
+            "payload must be at least {} bytes",
+            MIN_SIZE
+        );
+        let mut bytes = Vec::with_capacity(size);
+        bytes.extend_from_slice(&self.nonce.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.channel.to_be_bytes());
+        let mut state = self.nonce ^ self.sequence.rotate_left(16) ^ 0x9E37_79B9;
+        while bytes.len() < size - CHECKSUM_SIZE {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            bytes.push(state as u8);
+        }
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Payload, Error> {
+        if bytes.len() < MIN_SIZE {
+            return Err(Error::TooShort);
+        }
+        let payload = Payload {
+            nonce: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            sequence: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            channel: u16::from_be_bytes([bytes[8], bytes[9]]),
+        };
+        if payload.encode(bytes.len()) == bytes {
+            Ok(payload)
+        } else {
+            Err(Error::Checksum(payload))
+        }
+    }
+}
+
+/// Tells the packets of a run from those of any other
+pub fn run_nonce() -> u32 {
+    rand::random::<u32>()
+}
+
+// CRC-32/ISO-HDLC, as used by zip and ethernet
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,174 @@
+use super::report::ChannelResult;
+use serde::Serialize;
+
+/// RSSI seen by the control gateway while the tested gateway steps through
+/// its transmit power. Every dB more at the PA should be a dB more at the
+/// receiver; a broken PA or a wrong tx_gain_lut shows up as steps which
+/// stray from that line
+#[derive(Debug, Serialize)]
+pub struct PowerSweep {
+    pub steps: Vec<PowerStep>,
+    /// least squares fit of RSSI against power, in dB per dB
+    pub slope: Option<f64>,
+    pub intercept: Option<f64>,
+    /// largest accepted deviation from the 1 dB/dB line, in dB
+    pub max_deviation: f64,
+    /// largest accepted distance of the slope from 1 dB/dB
+    pub max_slope_error: f64,
+    /// whether the slope is missing or too far from 1 dB/dB
+    pub slope_flagged: bool,
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PowerStep {
+    /// in dBm
+    pub power: u64,
+    /// mean RSSI of the packets received at this power
+    pub rssi: Option<f64>,
+    /// distance from the 1 dB/dB line through the mean of all steps, in dB
+    pub deviation: Option<f64>,
+    pub flagged: bool,
+}
+
+impl PowerSweep {
+    pub fn from_results(
+        results: &[ChannelResult],
+        max_deviation: f64,
+        max_slope_error: f64,
+    ) -> PowerSweep {
+        let mut powers: Vec<u64> = results.iter().map(|result| result.power).collect();
+        powers.dedup();
+
+        let rssi = powers
+            .into_iter()
+            .map(|power| {
+                let rssi: Vec<f64> = results
+                    .iter()
+                    .filter(|result| result.power == power)
+                    .flat_map(|result| result.packets.iter())
+                    .filter_map(|packet| packet.rssi)
+                    .map(f64::from)
+                    .collect();
+                let mean = if rssi.is_empty() {
+                    None
+                } else {
+                    Some(rssi.iter().sum::<f64>() / rssi.len() as f64)
+                };
+                (power, mean)
+            })
+            .collect();
+        PowerSweep::from_rssi(rssi, max_deviation, max_slope_error)
+    }
+
+    /// The sweep of the mean RSSI received at each power, None where
+    /// nothing was
+    pub fn from_rssi(
+        rssi: Vec<(u64, Option<f64>)>,
+        max_deviation: f64,
+        max_slope_error: f64,
+    ) -> PowerSweep {
+        let mut steps: Vec<PowerStep> = rssi
+            .into_iter()
+            .map(|(power, rssi)| PowerStep {
+                power,
+                rssi,
+                deviation: None,
+                flagged: true,
+            })
+            .collect();
+
+        let points: Vec<(f64, f64)> = steps
+            .iter()
+            .filter_map(|step| step.rssi.map(|rssi| (step.power as f64, rssi)))
+            .collect();
+        let (slope, intercept) = match fit(&points) {
+            Some((slope, intercept)) => (Some(slope), Some(intercept)),
+            None => (None, None),
+        };
+
+        if !points.is_empty() {
+            let offset =
+                points.iter().map(|(power, rssi)| rssi - power).sum::<f64>() / points.len() as f64;
+            for step in steps.iter_mut() {
+                if let Some(rssi) = step.rssi {
+                    let deviation = rssi - (step.power as f64 + offset);
+                    step.deviation = Some(deviation);
+                    step.flagged = deviation.abs() > max_deviation;
+                }
+            }
+        }
+
+        // steps may each stay within max_deviation of the line while a
+        // compressing PA still bends it away from 1 dB/dB
+        let slope_flagged = slope.is_none_or(|slope| (slope - 1.0).abs() > max_slope_error);
+        let passed = !slope_flagged && steps.iter().all(|step| !step.flagged);
+        PowerSweep {
+            steps,
+            slope,
+            intercept,
+            max_deviation,
+            max_slope_error,
+            slope_flagged,
+            passed,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "\tPWR {:<12}{:<12}Deviation (dB)",
+            "Power (dBm)", "RSSI (dBm)"
+        );
+        for step in &self.steps {
+            summary.push_str(
+                format!(
+                    "\n\t    {:<12}{:<12}{:<16}{}",
+                    step.power,
+                    step.rssi
+                        .map(|rssi| format!("{:.1}", rssi))
+                        .unwrap_or_default(),
+                    step.deviation
+                        .map(|deviation| format!("{:+.1}", deviation))
+                        .unwrap_or_default(),
+                    if step.flagged { "FLAGGED" } else { "" }
+                )
+                .trim_end(),
+            );
+        }
+        match self.slope {
+            Some(slope) => summary.push_str(
+                format!(
+                    "\n\tSlope {:.2} dB/dB (expected 1.00 ± {}), max deviation {} dB {}",
+                    slope,
+                    self.max_slope_error,
+                    self.max_deviation,
+                    if self.slope_flagged { "FLAGGED" } else { "" }
+                )
+                .trim_end(),
+            ),
+            None => summary.push_str("\n\tNot enough steps received to fit a slope"),
+        }
+        summary
+    }
+}
+
+/// Least squares line through the points, as (slope, intercept). None
+/// without two points apart on x
+pub fn fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    if points.len() < 2 {
+        return None;
+    }
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    let slope = covariance / variance;
+    Some((slope, mean_y - slope * mean_x))
+}
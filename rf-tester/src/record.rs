@@ -0,0 +1,120 @@
+//! Sessions recorded with `--record`, one JSON object per line, for
+//! [`replay`](crate::replay) to analyze offline.
+//!
+//! Frames are kept byte for byte: datagrams from the gateways as they came,
+//! fields semtech-udp does not know included, and PULL_RESP as they are
+//! about to be sent, so one to a gateway which is not connected is recorded
+//! all the same. Each PULL_RESP says which test sent it and what for, so
+//! a replay does not have to guess. The server answers PULL_DATA and
+//! PUSH_DATA on its own, so those acknowledgements are left out.
+use super::report::{mac_to_string, now_ms};
+use semtech_udp::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// One frame through a server, at `at` in unix ms
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "direction", rename_all = "lowercase")]
+pub enum Entry {
+    /// from a gateway to the server on `port`
+    Up { at: u64, port: u16, frame: String },
+    /// a PULL_RESP to the gateway `mac`, whose packet `rx_mac` should hear,
+    /// sent by the `test` of the report, such as TX or SCHED
+    Down {
+        at: u64,
+        port: u16,
+        mac: String,
+        rx_mac: String,
+        test: String,
+        step: Step,
+        frame: String,
+    },
+}
+
+/// What a PULL_RESP is for within its test
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// a packet of the channel result
+    Packet,
+    /// the uplink of the control gateway a scheduled packet answers
+    Uplink,
+}
+
+impl Entry {
+    /// The frame as it went on the wire, if it is valid base64
+    pub fn frame(&self) -> Option<Vec<u8>> {
+        match self {
+            Entry::Up { frame, .. } | Entry::Down { frame, .. } => base64::decode(frame).ok(),
+        }
+    }
+}
+
+/// Appends the frames of a run to a file as they go
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        Ok(Recorder {
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// A datagram from a gateway, as it came
+    pub fn up(&self, port: u16, frame: &[u8]) {
+        self.write(&Entry::Up {
+            at: now_ms(),
+            port,
+            frame: base64::encode(frame),
+        });
+    }
+
+    pub fn down(
+        &self,
+        port: u16,
+        (mac, rx_mac): (&MacAddress, &MacAddress),
+        (test, step): (&str, Step),
+        frame: &[u8],
+    ) {
+        self.write(&Entry::Down {
+            at: now_ms(),
+            port,
+            mac: mac_to_string(mac),
+            rx_mac: mac_to_string(rx_mac),
+            test: test.to_string(),
+            step,
+            frame: base64::encode(frame),
+        });
+    }
+
+    fn write(&self, entry: &Entry) {
+        let mut file = self.file.lock().expect("no one panics holding the lock");
+        let written = serde_json::to_writer(&mut *file, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| file.write_all(b"\n"));
+        if let Err(e) = written {
+            println!("Could not record frame: {}", e);
+        }
+    }
+}
+
+/// The entries of a recording, in the order they were recorded
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line)?);
+    }
+    Ok(entries)
+}
@@ -0,0 +1,349 @@
+//! Offline analysis of a session recorded with `--record`.
+//!
+//! The frames of the gateways go through the same event handling as in a
+//! live run, and the packets they carry are matched the same way to the
+//! PULL_RESP sent before them. Consecutive packets of a test from one
+//! gateway to another on the same channel, data rate and power make up a
+//! channel result, filed under the test the recording gives. Only packets
+//! rf-tester tagged are analyzed, so beacons are left out, and scheduled
+//! packets are matched without checking their timing.
+use super::{
+    health::Monitor,
+    opt::{parse_eui, Opt},
+    payload::Payload,
+    record::{self, Entry, Step},
+    report::{self, DirectionReport, GatewayReport, Report},
+    tester::{self, Admission, Connection, Listener, Message, Role},
+    tx_error::TxError,
+    udp::{self, Clients},
+    wire,
+};
+use semtech_udp::{
+    server_runtime::{self, Event},
+    tx_ack, MacAddress, Up,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use structopt::StructOpt;
+use tokio::sync::mpsc;
+
+/// Options of `rf-tester replay`
+#[derive(Debug, StructOpt)]
+pub struct ReplayOpt {
+    /// the recording
+    #[structopt(parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// the options of the recorded run. They give the gateways their roles
+    /// and set the thresholds, which can be changed to analyze it again
+    #[structopt(flatten)]
+    pub opt: Opt,
+}
+
+// the packets sent on a channel from one gateway to another
+struct Channel {
+    direction: &'static str,
+    tx_mac: MacAddress,
+    rx_mac: MacAddress,
+    nonce: u32,
+    result: report::ChannelResult,
+}
+
+// the tests a recording can file packets under
+const TESTS: [&str; 8] = ["TX", "RX", "SCHED", "GPS", "BCN", "IQ", "CRC", "PWR"];
+
+// a PULL_RESP waiting for its TX_ACK, and the packet it carries if it is
+// one of ours, by channel and packet index
+type Pending = (MacAddress, Option<(usize, usize)>);
+
+struct Session {
+    monitor: Arc<Monitor>,
+    // gateways which sent a PULL_DATA, by port, as the servers know them
+    clients: HashMap<u16, Clients>,
+    gateways: Vec<Connection>,
+    channels: Vec<Channel>,
+    pending: Vec<Pending>,
+    // the gateway which should hear the uplink a scheduled packet answers,
+    // until that packet is sent
+    uplink_to: Option<MacAddress>,
+}
+
+/// Runs the recording at `path` through the test as configured by `cli`
+pub async fn replay(cli: &Opt, path: &Path) -> Result<Report, Box<dyn std::error::Error>> {
+    cli.check_thresholds()?;
+    cli.check_gateways()?;
+    let entries = record::read(path)?;
+
+    let (packet_tx, mut packet_rx) = mpsc::channel::<Message>(120);
+    let (connection_tx, mut connection_rx) = mpsc::channel::<Connection>(16);
+    let mut session = Session {
+        monitor: Arc::new(Monitor::new(Duration::from_secs(cli.max_keepalive_gap))),
+        clients: HashMap::new(),
+        gateways: Vec::new(),
+        channels: Vec::new(),
+        pending: Vec::new(),
+        uplink_to: None,
+    };
+    let mut listeners: HashMap<u16, Listener> = HashMap::new();
+
+    for entry in &entries {
+        let frame = entry
+            .frame()
+            .ok_or("the recording holds a frame which is not base64")?;
+        match entry {
+            Entry::Up { at, port, .. } => {
+                let listener = listeners.entry(*port).or_insert_with(|| Listener {
+                    admission: Admission::new(cli, *port),
+                    sender: packet_tx.clone(),
+                    connections: connection_tx.clone(),
+                    monitor: session.monitor.clone(),
+                    recorder: None,
+                    debug: cli.debug,
+                });
+                let events = session.events(*port, &frame, *at);
+                listener.handle(&frame, events).await;
+                while let Ok(connection) = connection_rx.try_recv() {
+                    if !session
+                        .gateways
+                        .iter()
+                        .any(|(mac, ..)| *mac == connection.0)
+                    {
+                        session.gateways.push(connection);
+                    }
+                }
+                while let Ok(message) = packet_rx.try_recv() {
+                    session.heard(message, *at);
+                }
+            }
+            Entry::Down {
+                mac,
+                rx_mac,
+                test,
+                step,
+                ..
+            } => session.sent(&frame, (&eui(mac)?, &eui(rx_mac)?), test, *step)?,
+        }
+    }
+    Ok(session.finish(cli))
+}
+
+fn eui(mac: &str) -> Result<MacAddress, String> {
+    parse_eui(mac).map(|mac| MacAddress::new(&mac.to_be_bytes()))
+}
+
+impl Session {
+    // the events the server on `port` raised for a frame. TX_ACK are
+    // answers to the PULL_RESP of the recording
+    fn events(&mut self, port: u16, frame: &[u8], at: u64) -> Vec<Event> {
+        // the address of the gateways is not recorded
+        let addr = SocketAddr::from(([0, 0, 0, 0], 0));
+        let events = udp::events(frame, addr, self.clients.entry(port).or_default());
+        for event in &events {
+            if let Event::RawPacket(Up::TxAck(tx_ack)) = event {
+                let outcome = match tx_ack.get_error() {
+                    Some(error) => Err(ack_error(error)),
+                    None => Ok(at),
+                };
+                self.resolve(&tx_ack.gateway_mac, outcome);
+            }
+        }
+        events
+    }
+
+    fn sent(
+        &mut self,
+        frame: &[u8],
+        (tx_mac, rx_mac): (&MacAddress, &MacAddress),
+        test: &str,
+        step: Step,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let txpk = match wire::parse_pull_resp(frame) {
+            Some((_, downlink)) => downlink.txpk,
+            None => return Err("the recording holds a downlink which is not a PULL_RESP".into()),
+        };
+        let direction = match TESTS.iter().find(|known| **known == test) {
+            Some(direction) => *direction,
+            None => {
+                return Err(
+                    format!("the recording holds a downlink of unknown test {}", test).into(),
+                )
+            }
+        };
+        // the previous one went unanswered
+        self.resolve(tx_mac, Err(TxError::AckTimeout));
+        self.uplink_to = Some(*rx_mac).filter(|_| step == Step::Uplink);
+        let payload = base64::decode(&txpk.data)
+            .ok()
+            .and_then(|data| Payload::decode(&data).ok())
+            // the uplink a scheduled packet answers is not one of the result
+            .filter(|_| step == Step::Packet);
+        let payload = match payload {
+            Some(payload) => payload,
+            None => {
+                self.pending.push((*tx_mac, None));
+                return Ok(());
+            }
+        };
+
+        let index = payload.channel as usize;
+        let frequency = (txpk.freq * 1_000_000.0).round() as usize;
+        let crc = txpk.ncrc != Some(true);
+        let same_channel = self.channels.last().is_some_and(|channel| {
+            channel.direction == direction
+                && channel.tx_mac == *tx_mac
+                && channel.rx_mac == *rx_mac
+                && channel.nonce == payload.nonce
+                && channel.result.index == index
+                && channel.result.frequency == frequency
+                && channel.result.datr == txpk.datr
+                && channel.result.power == txpk.powe
+                && channel.result.ipol == txpk.ipol
+                && channel.result.crc == crc
+        });
+        if !same_channel {
+            println!(
+                "{} channel {}: {} MHz, {}, {} dBm, from {} to {}",
+                direction,
+                index + 1,
+                txpk.freq,
+                txpk.datr,
+                txpk.powe,
+                report::mac_to_string(tx_mac),
+                report::mac_to_string(rx_mac)
+            );
+            let mut result =
+                tester::channel_result(tx_mac, rx_mac, index, frequency, &txpk.datr, txpk.powe);
+            result.ipol = txpk.ipol;
+            result.crc = crc;
+            self.channels.push(Channel {
+                direction,
+                tx_mac: *tx_mac,
+                rx_mac: *rx_mac,
+                nonce: payload.nonce,
+                result,
+            });
+        }
+
+        let channel = self.channels.len() - 1;
+        let packets = &mut self.channels[channel].result.packets;
+        // a retry goes with the packet it retries
+        if packets.last().map(|packet| packet.sequence) != Some(payload.sequence) {
+            packets.push(tester::packet_result(payload.sequence));
+        }
+        let packet = packets.len() - 1;
+        packets[packet].tx_error = None;
+        self.pending.push((*tx_mac, Some((channel, packet))));
+        Ok(())
+    }
+
+    // settles the PULL_RESP waiting for a TX_ACK from `mac`, if any: sent at
+    // the given time or failed
+    fn resolve(&mut self, mac: &MacAddress, outcome: Result<u64, TxError>) {
+        let index = match self.pending.iter().position(|(pending, _)| pending == mac) {
+            Some(index) => index,
+            None => return,
+        };
+        let (_, packet) = self.pending.remove(index);
+        self.monitor.record_downlink(mac, outcome.is_ok());
+        if let Some((channel, packet)) = packet {
+            let packet = &mut self.channels[channel].result.packets[packet];
+            match outcome {
+                Ok(at) => packet.sent_at = Some(at),
+                Err(error) => packet.tx_error = Some(error),
+            }
+        }
+    }
+
+    // matches a packet to the channel being tested, as a live run would
+    fn heard(&mut self, (rxpk, foff, mac, _): Message, at: u64) {
+        if self.uplink_to == Some(mac) {
+            return;
+        }
+        if let Some(channel) = self.channels.last_mut() {
+            if mac == channel.rx_mac {
+                tester::record_packet(&mut channel.result, channel.nonce, &rxpk, foff, at);
+            }
+        }
+    }
+
+    fn finish(mut self, cli: &Opt) -> Report {
+        while let Some((mac, _)) = self.pending.first().cloned() {
+            self.resolve(&mac, Err(TxError::AckTimeout));
+        }
+        let mut gateways: Vec<Connection> = self.gateways.clone();
+        // gateways under test first, in the order they were given
+        gateways.sort_by_key(|(mac, role, port)| {
+            (
+                *role != Role::Tested,
+                cli.gateway_order(role.clone(), mac, *port),
+            )
+        });
+
+        let mut reports: Vec<GatewayReport> = Vec::new();
+        let tested: Vec<MacAddress> = gateways
+            .iter()
+            .filter(|(_, role, _)| *role == Role::Tested)
+            .map(|(mac, ..)| *mac)
+            .collect();
+        for mac in &tested {
+            reports.push(GatewayReport {
+                mac: report::mac_to_string(mac),
+                directions: Vec::new(),
+                power_sweep: None,
+            });
+        }
+        for mut channel in std::mem::take(&mut self.channels) {
+            channel.result.finish(&cli.thresholds(channel.result.power));
+            let gateway = tested
+                .iter()
+                .position(|mac| *mac == channel.tx_mac || *mac == channel.rx_mac);
+            let directions = match gateway {
+                Some(gateway) => &mut reports[gateway].directions,
+                None => continue,
+            };
+            match directions
+                .iter_mut()
+                .find(|direction| direction.direction == channel.direction)
+            {
+                Some(direction) => direction.channels.push(channel.result),
+                None => directions.push(DirectionReport {
+                    direction: channel.direction,
+                    channels: vec![channel.result],
+                }),
+            }
+        }
+        for direction in reports
+            .iter_mut()
+            .flat_map(|gateway| &mut gateway.directions)
+        {
+            direction.check_iq_controls();
+        }
+        for gateway in &reports {
+            tester::print_results(cli, gateway);
+        }
+
+        let mut stats = Vec::new();
+        if cli.check_stats {
+            stats = gateways
+                .iter()
+                .map(|(mac, ..)| self.monitor.check(mac))
+                .collect();
+            tester::print_stat_checks(&stats);
+        }
+        Report {
+            region: format!("{:?}", cli.region),
+            gateways: reports,
+            stats,
+            soak: None,
+        }
+    }
+}
+
+fn ack_error(error: tx_ack::Error) -> TxError {
+    TxError::from(&server_runtime::Error::AckError(error))
+}
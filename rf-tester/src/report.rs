@@ -0,0 +1,756 @@
+use super::{health::StatCheck, power_sweep::PowerSweep, soak::Soak, tx_error::TxError};
+use semtech_udp::MacAddress;
+use serde::Serialize;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    /// the expected packet was received, or none was when packets should
+    /// not be heard
+    Pass,
+    /// the downlink could not be dispatched or the packet came back on the
+    /// wrong channel or data rate
+    Fail,
+    /// the packet was not received at all
+    Timeout,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = match self {
+            Verdict::Pass => "PASS",
+            Verdict::Fail => "FAIL",
+            Verdict::Timeout => "TIMEOUT",
+        };
+        f.pad(verdict)
+    }
+}
+
+/// What a channel must achieve to pass
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    /// highest packet error rate, from 0 to 1
+    pub max_per: f64,
+    /// largest mean frequency error, in ppm of the channel frequency
+    pub max_ppm: f64,
+    /// lowest RSSI of any packet, in dBm
+    pub min_rssi: Option<f64>,
+    /// lowest SNR of any packet, in dB
+    pub min_snr: Option<f64>,
+    /// lowest and highest RSSI of any packet, in dBm
+    pub rssi_window: Option<(f64, f64)>,
+}
+
+impl Thresholds {
+    /// How far the RSSI of the packets stays within the thresholds, in dB.
+    /// Negative when it falls outside
+    pub fn rssi_margin(&self, rssi: &Stats) -> Option<f64> {
+        let mut margins = Vec::new();
+        if let Some(min) = self.min_rssi {
+            margins.push(rssi.min - min);
+        }
+        if let Some((low, high)) = self.rssi_window {
+            margins.push(rssi.min - low);
+            margins.push(high - rssi.max);
+        }
+        margins.into_iter().reduce(f64::min)
+    }
+
+    /// How far the SNR of the packets stays above the minimum, in dB
+    pub fn snr_margin(&self, snr: &Stats) -> Option<f64> {
+        self.min_snr.map(|min| snr.min - min)
+    }
+}
+
+/// CRC status of a received packet, the `stat` of its rxpk
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrcStatus {
+    Ok,
+    Bad,
+    /// the packet was sent without a CRC
+    None,
+}
+
+impl CrcStatus {
+    /// 1 is OK, 0 no CRC and -1, which semtech-udp only takes as `u64::MAX`,
+    /// a failed CRC
+    pub fn from_stat(stat: u64) -> CrcStatus {
+        match stat {
+            1 => CrcStatus::Ok,
+            0 => CrcStatus::None,
+            _ => CrcStatus::Bad,
+        }
+    }
+}
+
+impl fmt::Display for CrcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            CrcStatus::Ok => "OK",
+            CrcStatus::Bad => "BAD",
+            CrcStatus::None => "NONE",
+        };
+        f.pad(status)
+    }
+}
+
+/// Outcome of sending packets on one channel
+#[derive(Debug, Serialize)]
+pub struct ChannelResult {
+    pub index: usize,
+    /// in Hz
+    pub frequency: usize,
+    pub datr: String,
+    pub power: u64,
+    /// whether the packets were sent with inverted IQ, in which case the
+    /// receiving gateway should not hear any of them
+    pub ipol: bool,
+    /// whether the packets were sent with a CRC
+    pub crc: bool,
+    pub verdict: Verdict,
+    pub tx_mac: String,
+    pub rx_mac: String,
+    /// packets successfully dispatched
+    pub sent: usize,
+    pub received: usize,
+    /// packets which came back on the wrong channel or data rate
+    pub mismatched: usize,
+    /// late packets that were sent on a previous channel
+    pub stale: usize,
+    /// packets received more than once
+    pub duplicates: usize,
+    /// packets of this run which failed their checksum
+    pub corrupted: usize,
+    /// packets not attempted after the gateway refused to transmit
+    pub skipped: usize,
+    /// packets received with another CRC status than they were sent with
+    pub wrong_crc: usize,
+    /// packet error rate, over every packet attempted
+    pub per: f64,
+    pub rssi: Option<Stats>,
+    pub snr: Option<Stats>,
+    /// how far the RSSI stays within the thresholds, in dB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi_margin: Option<f64>,
+    /// how far the SNR stays above its minimum, in dB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snr_margin: Option<f64>,
+    /// frequency errors of the packets received, in Hz
+    pub frequency_error: Option<Stats>,
+    /// mean frequency error, in ppm of the channel frequency
+    pub ppm: Option<f64>,
+    /// timing errors of scheduled transmissions, in ms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Stats>,
+    pub packets: Vec<PacketResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketResult {
+    pub sequence: u32,
+    /// unix time in ms at which the downlink was dispatched
+    pub sent_at: Option<u64>,
+    /// unix time in ms at which the packet was received back
+    pub received_at: Option<u64>,
+    pub rssi: Option<i32>,
+    pub snr: Option<f32>,
+    /// received minus sent frequency, in Hz
+    pub frequency_offset: Option<i64>,
+    /// offset from the received frequency measured by the demodulator, in
+    /// Hz, when the gateway reports one
+    pub foff: Option<i64>,
+    /// as reported by the receiving gateway
+    pub crc: Option<CrcStatus>,
+    /// why the packet was not transmitted, after any retries
+    pub tx_error: Option<TxError>,
+    /// how much later than scheduled the packet was received, in ms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing_error: Option<i64>,
+}
+
+impl PacketResult {
+    /// How far from the sent frequency the packet really was, in Hz.
+    /// Receivers report the centre of the channel they heard it on, so this
+    /// takes the offset their demodulator measured (foff) as well
+    pub fn frequency_error(&self) -> Option<i64> {
+        Some(self.frequency_offset? + self.foff?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Stats {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    pub fn from_samples(samples: &[f64]) -> Option<Stats> {
+        if samples.is_empty() {
+            return None;
+        }
+        let count = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let variance = samples
+            .iter()
+            .map(|sample| (sample - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Some(Stats {
+            min: samples.iter().cloned().fold(f64::INFINITY, f64::min),
+            mean,
+            max: samples.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&format!(
+            "{:.1} [{:.1}, {:.1}] σ {:.1}",
+            self.mean, self.min, self.max, self.stddev
+        ))
+    }
+}
+
+impl ChannelResult {
+    pub fn statistics(&self) -> String {
+        let mut statistics = format!(
+            "received {}/{}, PER = {:.1}%, RSSI = {}, SNR = {}",
+            self.received,
+            self.packets.len(),
+            self.per * 100.0,
+            or_empty(self.rssi),
+            or_empty(self.snr)
+        );
+        for (margin, label) in &[(self.rssi_margin, "RSSI"), (self.snr_margin, "SNR")] {
+            if let Some(margin) = margin {
+                statistics.push_str(&format!(", {} margin = {:+.1} dB", label, margin));
+            }
+        }
+        if let (Some(frequency_error), Some(ppm)) = (self.frequency_error, self.ppm) {
+            statistics.push_str(&format!(
+                ", frequency error = {} Hz ({:+.2} ppm)",
+                frequency_error, ppm
+            ));
+        } else if self.received > 0 {
+            statistics.push_str(", frequency error unmeasured");
+        }
+        if let Some(timing) = self.timing {
+            statistics.push_str(&format!(", timing error = {} ms", timing));
+        }
+        for (count, label) in &[
+            (self.mismatched, "mismatched"),
+            (self.stale, "stale"),
+            (self.duplicates, "duplicate"),
+            (self.corrupted, "corrupted"),
+            (self.skipped, "skipped"),
+            (self.wrong_crc, "with wrong CRC status"),
+        ] {
+            if *count > 0 {
+                statistics.push_str(&format!(", {} {}", count, label));
+            }
+        }
+        for error in self.tx_errors() {
+            let count = self
+                .packets
+                .iter()
+                .filter(|packet| packet.tx_error == Some(error))
+                .count();
+            statistics.push_str(&format!(
+                ", {} not transmitted ({}: {})",
+                count,
+                error,
+                error.description()
+            ));
+        }
+        statistics
+    }
+
+    /// RSSI and SNR margins as "+5.0/+2.5", or nothing without thresholds
+    pub fn margins(&self) -> String {
+        if self.rssi_margin.is_none() && self.snr_margin.is_none() {
+            return String::new();
+        }
+        let margin = |margin: Option<f64>| or_empty(margin.map(|margin| format!("{:+.1}", margin)));
+        format!("{}/{}", margin(self.rssi_margin), margin(self.snr_margin))
+    }
+
+    /// Mean frequency error as "+1.73" ppm, "n/a" when packets were heard
+    /// but the receiver did not report foff, or nothing
+    pub fn ppm_cell(&self) -> String {
+        match self.ppm {
+            Some(ppm) => format!("{:+.2}", ppm),
+            None if self.received > 0 => "n/a".to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// Every kind of TX error met on the channel, in order of appearance
+    pub fn tx_errors(&self) -> Vec<TxError> {
+        let mut errors = Vec::new();
+        for error in self.packets.iter().filter_map(|packet| packet.tx_error) {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    /// Computes the statistics and the verdict once all packets are in
+    pub fn finish(&mut self, thresholds: &Thresholds) {
+        let received: Vec<&PacketResult> = self
+            .packets
+            .iter()
+            .filter(|packet| packet.received_at.is_some())
+            .collect();
+        self.sent = self
+            .packets
+            .iter()
+            .filter(|packet| packet.sent_at.is_some())
+            .count();
+        self.received = received.len();
+        self.per = if self.packets.is_empty() {
+            1.0
+        } else {
+            1.0 - self.received as f64 / self.packets.len() as f64
+        };
+        let rssi: Vec<f64> = received
+            .iter()
+            .filter_map(|packet| packet.rssi)
+            .map(f64::from)
+            .collect();
+        let snr: Vec<f64> = received
+            .iter()
+            .filter_map(|packet| packet.snr)
+            .map(f64::from)
+            .collect();
+        let timing: Vec<f64> = received
+            .iter()
+            .filter_map(|packet| packet.timing_error)
+            .map(|timing_error| timing_error as f64)
+            .collect();
+        let frequency_error: Vec<f64> = received
+            .iter()
+            .filter_map(|packet| packet.frequency_error())
+            .map(|frequency_error| frequency_error as f64)
+            .collect();
+        self.rssi = Stats::from_samples(&rssi);
+        self.snr = Stats::from_samples(&snr);
+        self.frequency_error = Stats::from_samples(&frequency_error);
+        self.ppm = self
+            .frequency_error
+            .map(|frequency_error| frequency_error.mean / self.frequency as f64 * 1_000_000.0);
+        self.timing = Stats::from_samples(&timing);
+        self.rssi_margin = self.rssi.and_then(|rssi| thresholds.rssi_margin(&rssi));
+        self.snr_margin = self.snr.and_then(|snr| thresholds.snr_margin(&snr));
+        let expected_crc = if self.crc {
+            CrcStatus::Ok
+        } else {
+            CrcStatus::None
+        };
+        self.wrong_crc = received
+            .iter()
+            .filter(|packet| packet.crc.is_some_and(|crc| crc != expected_crc))
+            .count();
+
+        self.verdict = if self.ipol {
+            // anything heard at all means the polarity was ignored
+            if self.sent > 0 && self.received == 0 && self.mismatched == 0 {
+                Verdict::Pass
+            } else {
+                Verdict::Fail
+            }
+        } else if self.received == 0 && self.mismatched == 0 && self.sent > 0 {
+            Verdict::Timeout
+        } else if self.received > 0
+            && self.per <= thresholds.max_per
+            && self.wrong_crc == 0
+            && self.ppm.is_none_or(|ppm| ppm.abs() <= thresholds.max_ppm)
+            && self.rssi_margin.is_none_or(|margin| margin >= 0.0)
+            && self.snr_margin.is_none_or(|margin| margin >= 0.0)
+        {
+            Verdict::Pass
+        } else {
+            Verdict::Fail
+        };
+    }
+}
+
+/// Results of a test direction: TX tests the gateway under test sending to
+/// the control gateway, RX the other way around
+#[derive(Debug, Serialize)]
+pub struct DirectionReport {
+    pub direction: &'static str,
+    pub channels: Vec<ChannelResult>,
+}
+
+impl DirectionReport {
+    /// Table of the verdicts, as printed at the end of a run
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "\t{:<4}{:<9}{:<14}{:<9}{:<9}{:<28}{:<28}{:<20}{:<10}TX errors",
+            self.direction,
+            "Channel",
+            "Freq (MHz)",
+            "Result",
+            "PER",
+            "RSSI (mean [min, max] σ)",
+            "SNR (mean [min, max] σ)",
+            "Margin RSSI/SNR",
+            "ppm"
+        );
+        for result in &self.channels {
+            let tx_errors: Vec<String> =
+                result.tx_errors().iter().map(TxError::to_string).collect();
+            summary.push_str(
+                format!(
+                    "\n\t{:<4}{:<9}{:<14}{:<9}{:<9}{:<28}{:<28}{:<20}{:<10}{}",
+                    if result.ipol { "INV" } else { "" },
+                    result.index + 1,
+                    result.frequency as f64 / 1_000_000.0,
+                    result.verdict,
+                    format!("{:.1}%", result.per * 100.0),
+                    or_empty(result.rssi),
+                    or_empty(result.snr),
+                    result.margins(),
+                    result.ppm_cell(),
+                    tx_errors.join(", "),
+                )
+                .trim_end(),
+            );
+        }
+        summary
+    }
+
+    /// Fails the inverted IQ channels whose link did not hear the normal IQ
+    /// packets sent on the same channel: hearing nothing only shows that the
+    /// polarity was rejected if the receiver could hear at all
+    pub fn check_iq_controls(&mut self) {
+        let heard: Vec<(String, String, usize)> = self
+            .channels
+            .iter()
+            .filter(|result| !result.ipol && result.received > 0)
+            .map(|result| {
+                (
+                    result.tx_mac.clone(),
+                    result.rx_mac.clone(),
+                    result.frequency,
+                )
+            })
+            .collect();
+        for result in self.channels.iter_mut().filter(|result| result.ipol) {
+            let link = (
+                result.tx_mac.clone(),
+                result.rx_mac.clone(),
+                result.frequency,
+            );
+            if !heard.contains(&link) {
+                result.verdict = Verdict::Fail;
+            }
+        }
+    }
+}
+
+impl DirectionReport {
+    /// Channel × data rate table of the share of packets received, with the
+    /// mean RSSI/SNR, as printed at the end of a sweep
+    pub fn matrix(&self) -> String {
+        let mut data_rates: Vec<&str> = Vec::new();
+        let mut frequencies: Vec<usize> = Vec::new();
+        for result in &self.channels {
+            if !data_rates.contains(&result.datr.as_str()) {
+                data_rates.push(&result.datr);
+            }
+            if !frequencies.contains(&result.frequency) {
+                frequencies.push(result.frequency);
+            }
+        }
+        frequencies.sort_unstable();
+
+        let mut matrix = format!("\t{:<4}{:<12}", self.direction, "Freq (MHz)");
+        for data_rate in &data_rates {
+            matrix.push_str(&format!("{:<20}", data_rate));
+        }
+        for frequency in frequencies {
+            matrix.push_str(&format!(
+                "\n\t{:<4}{:<12}",
+                "",
+                frequency as f64 / 1_000_000.0
+            ));
+            for data_rate in &data_rates {
+                let cell = match self
+                    .channels
+                    .iter()
+                    .find(|result| result.frequency == frequency && result.datr == *data_rate)
+                {
+                    Some(result) => match (result.rssi, result.snr) {
+                        (Some(rssi), Some(snr)) => format!(
+                            "{:.0}% {:.0}/{:.1}",
+                            (1.0 - result.per) * 100.0,
+                            rssi.mean,
+                            snr.mean
+                        ),
+                        _ => format!("{:.0}%", (1.0 - result.per) * 100.0),
+                    },
+                    None => "-".to_string(),
+                };
+                matrix.push_str(&format!("{:<20}", cell));
+            }
+        }
+        matrix
+            .lines()
+            .map(str::trim_end)
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+}
+
+/// Results of one gateway under test, against every reference gateway
+#[derive(Debug, Serialize)]
+pub struct GatewayReport {
+    pub mac: String,
+    pub directions: Vec<DirectionReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_sweep: Option<PowerSweep>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub region: String,
+    pub gateways: Vec<GatewayReport>,
+    /// one per gateway, with --check-stats
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stats: Vec<StatCheck>,
+    /// instead of the gateways' results, with --soak
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub soak: Option<Soak>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Json,
+    Junit,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReportFormat, String> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "junit" => Ok(ReportFormat::Junit),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+/// Gateway EUI as plain hex. MacAddress' own Display skips a byte
+pub fn mac_to_string(mac: &MacAddress) -> String {
+    mac.bytes()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.gateways.iter().all(GatewayReport::passed)
+            && self.stats.iter().all(|check| check.passed)
+            && self.soak.as_ref().is_none_or(Soak::passed)
+    }
+
+    pub fn write(&self, format: ReportFormat, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        match format {
+            ReportFormat::Json => serde_json::to_writer_pretty(&mut file, self)?,
+            ReportFormat::Junit => file.write_all(self.to_junit().as_bytes())?,
+        }
+        Ok(())
+    }
+
+    /// One testsuite per gateway and direction and one testcase per channel.
+    /// Timeouts are reported as failures too since JUnit has no notion of them
+    pub fn to_junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+        for gateway in &self.gateways {
+            gateway.push_junit(&self.region, &mut xml);
+        }
+        if let Some(soak) = &self.soak {
+            soak.push_junit(&self.region, &mut xml);
+        }
+        if !self.stats.is_empty() {
+            let failures = self.stats.iter().filter(|check| !check.passed).count();
+            xml.push_str(&format!(
+                "  <testsuite name=\"{} stat counters\" tests=\"{}\" failures=\"{}\">\n",
+                escape(&self.region),
+                self.stats.len(),
+                failures
+            ));
+            for check in &self.stats {
+                xml.push_str(&format!(
+                    "    <testcase classname=\"rf-tester.STAT\" name=\"{}\">\n",
+                    check.mac
+                ));
+                if check.passed {
+                    xml.push_str(&format!(
+                        "      <system-out>{}</system-out>\n",
+                        escape(&check.summary())
+                    ));
+                } else {
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\" type=\"MISMATCH\"/>\n",
+                        escape(&check.summary())
+                    ));
+                }
+                xml.push_str("    </testcase>\n");
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+}
+
+impl GatewayReport {
+    pub fn passed(&self) -> bool {
+        self.directions
+            .iter()
+            .flat_map(|direction| direction.channels.iter())
+            .all(|result| result.verdict == Verdict::Pass)
+            && self
+                .power_sweep
+                .as_ref()
+                .is_none_or(|power_sweep| power_sweep.passed)
+    }
+
+    fn push_junit(&self, region: &str, xml: &mut String) {
+        for direction in &self.directions {
+            let failures = direction
+                .channels
+                .iter()
+                .filter(|result| result.verdict != Verdict::Pass)
+                .count();
+            xml.push_str(&format!(
+                "  <testsuite name=\"{} {} {}\" tests=\"{}\" failures=\"{}\">\n",
+                escape(region),
+                self.mac,
+                direction.direction,
+                direction.channels.len(),
+                failures
+            ));
+            for result in &direction.channels {
+                xml.push_str(&format!(
+                    "    <testcase classname=\"rf-tester.{}\" name=\"channel {} ({} MHz, {}, {} dBm) to {}\"",
+                    direction.direction,
+                    result.index + 1,
+                    result.frequency as f64 / 1_000_000.0,
+                    escape(&result.datr),
+                    result.power,
+                    result.rx_mac
+                ));
+                match result.verdict {
+                    Verdict::Pass => xml.push_str(&format!(
+                        ">\n      <system-out>{}</system-out>\n    </testcase>\n",
+                        escape(&result.statistics())
+                    )),
+                    verdict => xml.push_str(&format!(
+                        ">\n      <failure message=\"{}\" type=\"{}\"/>\n    </testcase>\n",
+                        escape(&format!(
+                            "{} from {} to {}: {}",
+                            verdict,
+                            result.tx_mac,
+                            result.rx_mac,
+                            result.statistics()
+                        )),
+                        verdict
+                    )),
+                }
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        if let Some(power_sweep) = &self.power_sweep {
+            let flagged = power_sweep.steps.iter().filter(|step| step.flagged).count()
+                + power_sweep.slope_flagged as usize;
+            xml.push_str(&format!(
+                "  <testsuite name=\"{} {} power linearity\" tests=\"{}\" failures=\"{}\">\n",
+                escape(region),
+                self.mac,
+                power_sweep.steps.len() + 1,
+                flagged
+            ));
+            xml.push_str("    <testcase classname=\"rf-tester.PWR\" name=\"slope\">\n");
+            let details = format!(
+                "slope = {} dB/dB, expected 1.00 ± {}",
+                or_empty(power_sweep.slope.map(|slope| format!("{:.2}", slope))),
+                power_sweep.max_slope_error
+            );
+            if power_sweep.slope_flagged {
+                xml.push_str(&format!(
+                    "      <failure message=\"{}\" type=\"FLAGGED\"/>\n",
+                    escape(&details)
+                ));
+            } else {
+                xml.push_str(&format!(
+                    "      <system-out>{}</system-out>\n",
+                    escape(&details)
+                ));
+            }
+            xml.push_str("    </testcase>\n");
+            for step in &power_sweep.steps {
+                xml.push_str(&format!(
+                    "    <testcase classname=\"rf-tester.PWR\" name=\"{} dBm\">\n",
+                    step.power
+                ));
+                let details = format!(
+                    "RSSI = {}, deviation = {} dB, slope = {} dB/dB",
+                    or_empty(step.rssi.map(|rssi| format!("{:.1}", rssi))),
+                    or_empty(step.deviation.map(|deviation| format!("{:+.1}", deviation))),
+                    or_empty(power_sweep.slope.map(|slope| format!("{:.2}", slope)))
+                );
+                if step.flagged {
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\" type=\"FLAGGED\"/>\n",
+                        escape(&details)
+                    ));
+                } else {
+                    xml.push_str(&format!(
+                        "      <system-out>{}</system-out>\n",
+                        escape(&details)
+                    ));
+                }
+                xml.push_str("    </testcase>\n");
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+    }
+}
+
+pub fn or_empty<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,418 @@
+//! Simulated packet forwarders, so that rf-tester can run without hardware.
+//!
+//! Every simulated gateway speaks the Semtech UDP protocol to its server:
+//! it keeps the downlink route open with PULL_DATA, acknowledges PULL_RESP
+//! with TX_ACK and forwards what it hears as PUSH_DATA. What one gateway
+//! transmits goes through the [`Medium`], which hands it to all the others.
+//! Packets scheduled with a `tmst` or a `tmms` are held until the gateway's
+//! counter or GPS time gets there, and are heard once they have been on air
+//! in full. Like concentrators listening for uplinks, the gateways only hear
+//! packets sent with normal IQ polarity. Every stat interval, they report
+//! what they counted since their previous report.
+use super::{
+    gps,
+    report::{mac_to_string, now_ms},
+    wire::{self, Downlink},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use regions::DataRate;
+use semtech_udp::{
+    pull_data, pull_resp,
+    push_data::{self, RxPk, RxPkV1},
+    tx_ack, MacAddress, Packet, SerializablePacket, StringOrNum,
+};
+use serde_json::json;
+use std::{
+    io,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc,
+    time::{self, Duration, Instant},
+};
+
+/// How packets travel between the simulated gateways
+#[derive(Debug, Clone)]
+pub struct Medium {
+    /// probability, from 0 to 1, that a gateway misses a packet
+    pub loss: f64,
+    /// RSSI of a packet sent at [`REFERENCE_POWER`]. Every dB more at the
+    /// PA is a dB more at the receiver
+    pub rssi: i32,
+    pub snr: f32,
+    /// how far from its channel every packet received is, in Hz. Up to
+    /// [`OFF_CHANNEL`], the gateways report it as the demodulator's offset
+    /// (foff) and the channel's centre as the frequency. Beyond, the packet
+    /// is picked up at the shifted frequency
+    pub frequency_error: i64,
+    /// whether the gateways report foff, as SX1302 ones do
+    pub foff: bool,
+    /// seeds the loss draws so that a run can be reproduced
+    pub seed: u64,
+    /// whether the gateways have a GPS fix. Without one, they refuse
+    /// packets scheduled at a GPS time
+    pub gps: bool,
+    /// how often the gateways send a `stat` report, in s. None when 0
+    pub stat_interval: u64,
+}
+
+impl Default for Medium {
+    fn default() -> Medium {
+        Medium {
+            loss: 0.0,
+            rssi: -60,
+            snr: 9.5,
+            frequency_error: 0,
+            foff: true,
+            seed: 0,
+            gps: true,
+            stat_interval: 30,
+        }
+    }
+}
+
+/// A simulated packet forwarder and the server it reports to
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    pub mac: MacAddress,
+    pub server: SocketAddr,
+}
+
+impl Gateway {
+    pub fn new(mac: u64, server: SocketAddr) -> Gateway {
+        Gateway {
+            mac: MacAddress::new(&mac.to_be_bytes()),
+            server,
+        }
+    }
+}
+
+// a txpk on its way through the medium, from the gateway at index `from`
+type Transmission = (usize, pull_resp::TxPk);
+
+// what a gateway counts between two stat reports
+#[derive(Debug, Default)]
+struct Counters {
+    rxnb: u64,
+    rxok: u64,
+    dwnb: u64,
+    txnb: u64,
+}
+
+impl Counters {
+    // reports the counters and starts over
+    fn report(&mut self) -> push_data::Stat {
+        let Counters {
+            rxnb,
+            rxok,
+            dwnb,
+            txnb,
+        } = std::mem::take(self);
+        serde_json::from_value(json!({
+            "time": stat_time(now_ms()),
+            "rxnb": rxnb,
+            "rxok": rxok,
+            "rxfw": rxnb,
+            "ackr": 100.0,
+            "dwnb": dwnb,
+            "txnb": txnb,
+        }))
+        .expect("a stat report is well formed")
+    }
+}
+
+const KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Largest frequency error, in Hz, at which a packet is still heard on the
+/// channel it was sent on
+pub const OFF_CHANNEL: i64 = 100_000;
+
+/// Transmit power, in dBm, at which packets are heard at [`Medium::rssi`].
+/// It is the default of `--power`
+pub const REFERENCE_POWER: u64 = 12;
+
+impl Medium {
+    /// Starts the gateways and the medium between them. They keep running
+    /// in the background for as long as the runtime does
+    pub async fn spawn(self, gateways: Vec<Gateway>) -> io::Result<()> {
+        let (transmissions, mut on_air) = mpsc::channel::<Transmission>(100);
+        let mut receivers = Vec::new();
+        let mut tallies = Vec::new();
+        let start = Instant::now();
+
+        for (index, gateway) in gateways.into_iter().enumerate() {
+            let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+            socket.connect(gateway.server).await?;
+            let (mut socket_rx, mut socket_tx) = socket.split();
+            let (uplinks, mut outgoing) = mpsc::channel::<Packet>(100);
+            receivers.push(uplinks.clone());
+            let counters = Arc::new(Mutex::new(Counters::default()));
+            tallies.push(counters.clone());
+
+            // the only task writing to the socket, it signs everything with
+            // the MAC of this gateway
+            let mac = gateway.mac;
+            let foff = self.offset().filter(|_| self.foff);
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 1024];
+                while let Some(mut packet) = outgoing.recv().await {
+                    if let Packet::Up(up) = &mut packet {
+                        up.set_gateway_mac(mac);
+                    }
+                    match packet.serialize(&mut buf) {
+                        Ok(n) => {
+                            let frame = wire::as_forwarded(&buf[..n as usize], foff);
+                            if let Err(e) = socket_tx.send(&frame).await {
+                                println!(
+                                    "Simulated gateway {} socket error: {}",
+                                    mac_to_string(&mac),
+                                    e
+                                );
+                            }
+                        }
+                        Err(e) => println!("Simulated gateway serialization error: {:?}", e),
+                    }
+                }
+            });
+
+            let mut keepalive = uplinks.clone();
+            tokio::spawn(async move {
+                loop {
+                    let pull_data = pull_data::Packet {
+                        random_token: rand::random(),
+                        gateway_mac: mac,
+                    };
+                    if keepalive.send(pull_data.into()).await.is_err() {
+                        return;
+                    }
+                    time::delay_for(KEEPALIVE).await;
+                }
+            });
+
+            if self.stat_interval > 0 {
+                let mut reports = uplinks.clone();
+                let counters = counters.clone();
+                let interval = Duration::from_secs(self.stat_interval);
+                tokio::spawn(async move {
+                    loop {
+                        time::delay_for(interval).await;
+                        let stat = counters
+                            .lock()
+                            .expect("no one panics holding the lock")
+                            .report();
+                        let push_data = push_data::Packet {
+                            random_token: rand::random(),
+                            gateway_mac: mac,
+                            data: push_data::Data {
+                                rxpk: None,
+                                stat: Some(stat),
+                            },
+                        };
+                        if reports.send(push_data.into()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+
+            let mut acks = uplinks;
+            let gps = self.gps;
+            let transmissions = transmissions.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 1024];
+                loop {
+                    let n = match socket_rx.recv(&mut buf).await {
+                        Ok(n) => n,
+                        // PULL_DATA sent before the server was up, keep
+                        // listening as packet forwarders do
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => continue,
+                        Err(_) => return,
+                    };
+                    if let Some((token, downlink)) = wire::parse_pull_resp(&buf[..n]) {
+                        counters
+                            .lock()
+                            .expect("no one panics holding the lock")
+                            .dwnb += 1;
+                        let scheduled = supported(&downlink.txpk)
+                            .and_then(|()| schedule(&downlink, counter(start, index), gps));
+                        let tx_ack = tx_ack::Packet {
+                            random_token: token,
+                            gateway_mac: mac,
+                            data: scheduled.err().map(tx_ack::TxPkNack::new),
+                        };
+                        if acks.send(tx_ack.into()).await.is_err() {
+                            return;
+                        }
+                        let delay = match scheduled {
+                            Ok(delay) => delay,
+                            Err(_) => continue,
+                        };
+                        let mut transmissions = transmissions.clone();
+                        let counters = counters.clone();
+                        tokio::spawn(async move {
+                            time::delay_for(delay).await;
+                            counters
+                                .lock()
+                                .expect("no one panics holding the lock")
+                                .txnb += 1;
+                            time::delay_for(time_on_air(&downlink)).await;
+                            let _ = transmissions.send((index, downlink.txpk)).await;
+                        });
+                    }
+                }
+            });
+        }
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        tokio::spawn(async move {
+            while let Some((from, txpk)) = on_air.recv().await {
+                for (index, receiver) in receivers.iter_mut().enumerate() {
+                    if index == from || txpk.ipol || rng.gen::<f64>() < self.loss {
+                        continue;
+                    }
+                    let rxpk = self.receive(&txpk, counter(start, index));
+                    {
+                        let mut counters = tallies[index]
+                            .lock()
+                            .expect("no one panics holding the lock");
+                        counters.rxnb += 1;
+                        if txpk.ncrc != Some(true) {
+                            counters.rxok += 1;
+                        }
+                    }
+                    let mut push_data = push_data::Packet::from_rxpk(rxpk);
+                    push_data.random_token = rand::random();
+                    let _ = receiver.send(push_data.into()).await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    // the frequency error as the demodulator measures it, when the packet
+    // is heard on its channel
+    fn offset(&self) -> Option<i64> {
+        Some(self.frequency_error).filter(|error| error.abs() < OFF_CHANNEL)
+    }
+
+    // what a gateway reports when it hears `txpk` at `tmst` on its counter
+    fn receive(&self, txpk: &pull_resp::TxPk, tmst: u32) -> RxPk {
+        let freq = match self.offset() {
+            Some(_) => txpk.freq,
+            None => txpk.freq + self.frequency_error as f64 / 1_000_000.0,
+        };
+        RxPk::V1(RxPkV1 {
+            chan: 0,
+            codr: txpk.codr.clone(),
+            data: txpk.data.clone(),
+            datr: txpk.datr.clone(),
+            freq,
+            lsnr: self.snr,
+            modu: txpk.modu.clone(),
+            rfch: 0,
+            rssi: self.rssi + txpk.powe as i32 - REFERENCE_POWER as i32,
+            size: txpk.size,
+            // forwarded as if forward_crc_disabled were set
+            stat: if txpk.ncrc == Some(true) { 0 } else { 1 },
+            tmst: tmst.into(),
+        })
+    }
+}
+
+// concentrator counter of the gateway at `index`, in µs. Each one starts from
+// a different value, as they would on real hardware
+fn counter(start: Instant, index: usize) -> u32 {
+    (start.elapsed().as_micros() as u32).wrapping_add((index as u32).wrapping_mul(0x1000_0000))
+}
+
+// whether the concentrator can transmit `txpk`. An FSK bitrate which did not
+// come as a number reads as 0
+fn supported(txpk: &pull_resp::TxPk) -> Result<(), tx_ack::Error> {
+    match DataRate::from_str(&txpk.datr) {
+        Ok(DataRate::Fsk { bitrate }) if !(500..=250_000).contains(&bitrate) => {
+            Err(tx_ack::Error::SEND_FAIL)
+        }
+        Ok(_) => Ok(()),
+        Err(_) => Err(tx_ack::Error::SEND_FAIL),
+    }
+}
+
+// how long to hold a downlink before transmitting it, or why it can't be. As
+// in the reference packet forwarder, a tmst wins over a tmms
+fn schedule(downlink: &Downlink, now: u32, gps: bool) -> Result<Duration, tx_ack::Error> {
+    let txpk = &downlink.txpk;
+    if txpk.imme {
+        return Ok(Duration::from_secs(0));
+    }
+    if downlink.tmst {
+        let tmst = match txpk.tmst {
+            StringOrNum::N(tmst) => tmst as u32,
+            StringOrNum::S(_) => return Err(tx_ack::Error::SEND_FAIL),
+        };
+        let wait = tmst.wrapping_sub(now);
+        // anything more than half the counter ahead is really behind
+        if wait > u32::MAX / 2 {
+            return Err(tx_ack::Error::TOO_LATE);
+        }
+        return Ok(Duration::from_micros(wait.into()));
+    }
+    if let Some(tmms) = &txpk.tmms {
+        let tmms = match tmms {
+            StringOrNum::N(tmms) => *tmms,
+            StringOrNum::S(_) => return Err(tx_ack::Error::SEND_FAIL),
+        };
+        if !gps {
+            return Err(tx_ack::Error::GPS_UNLOCKED);
+        }
+        let now = gps::gps_ms(now_ms());
+        if tmms <= now {
+            return Err(tx_ack::Error::TOO_LATE);
+        }
+        return Ok(Duration::from_millis(tmms - now));
+    }
+    // neither immediate nor timestamped
+    Err(tx_ack::Error::SEND_FAIL)
+}
+
+// the time of a stat report, as packet forwarders write it
+fn stat_time(unix_ms: u64) -> String {
+    let seconds = unix_ms / 1000;
+    let (hour, minute, second) = (seconds % 86_400 / 3600, seconds % 3600 / 60, seconds % 60);
+    // days since 1970 to a civil date, after Howard Hinnant's algorithm
+    let days = seconds / 86_400 + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} GMT",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn time_on_air(downlink: &Downlink) -> Duration {
+    let txpk = &downlink.txpk;
+    let preamble = txpk.prea.unwrap_or(8) as usize;
+    let crc = txpk.ncrc != Some(true);
+    DataRate::from_str(&txpk.datr)
+        .map(|data_rate| {
+            data_rate.frame_time_on_air(
+                txpk.size as usize,
+                preamble,
+                !downlink.implicit_header,
+                crc,
+            )
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,286 @@
+//! Long runs which repeat the TX and RX directions for hours, to catch what
+//! only shows over time: thermal drift, packet forwarders which crash or
+//! lose their PULL_DATA. Results are summed up period after period, an hour
+//! by default, and each period is logged as soon as it ends.
+use super::{
+    health::GatewayHealth,
+    report::{escape, ChannelResult, PacketResult, Stats},
+};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+};
+
+#[derive(Debug, Serialize)]
+pub struct Soak {
+    /// in s
+    pub duration: u64,
+    /// length of a period, in s
+    pub period: u64,
+    pub periods: Vec<SoakPeriod>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SoakPeriod {
+    /// unix time in ms
+    pub start: u64,
+    pub end: u64,
+    pub links: Vec<LinkPeriod>,
+    pub gateways: Vec<GatewayHealth>,
+    pub passed: bool,
+}
+
+/// One direction between two gateways over a period
+#[derive(Debug, Serialize)]
+pub struct LinkPeriod {
+    pub direction: &'static str,
+    pub tx_mac: String,
+    pub rx_mac: String,
+    pub sent: usize,
+    pub received: usize,
+    pub per: f64,
+    pub rssi: Option<Stats>,
+    pub snr: Option<Stats>,
+    /// change of the mean RSSI since the first period, in dB
+    pub rssi_drift: Option<f64>,
+    pub passed: bool,
+}
+
+impl Soak {
+    pub fn passed(&self) -> bool {
+        self.periods.iter().all(|period| period.passed)
+    }
+
+    /// One testcase per period for each link and each gateway
+    pub(crate) fn push_junit(&self, region: &str, xml: &mut String) {
+        let tests = self
+            .periods
+            .iter()
+            .map(|period| period.links.len() + period.gateways.len())
+            .sum::<usize>();
+        let failures = self
+            .periods
+            .iter()
+            .map(|period| {
+                period.links.iter().filter(|link| !link.passed).count()
+                    + period
+                        .gateways
+                        .iter()
+                        .filter(|gateway| !gateway.passed)
+                        .count()
+            })
+            .sum::<usize>();
+        xml.push_str(&format!(
+            "  <testsuite name=\"{} soak\" tests=\"{}\" failures=\"{}\">\n",
+            escape(region),
+            tests,
+            failures
+        ));
+        for (number, period) in self.periods.iter().enumerate() {
+            let mut cases: Vec<(String, String, bool, &str)> = period
+                .links
+                .iter()
+                .map(|link| {
+                    (
+                        format!("{} {} to {}", link.direction, link.tx_mac, link.rx_mac),
+                        link.summary(),
+                        link.passed,
+                        "PER",
+                    )
+                })
+                .collect();
+            cases.extend(period.gateways.iter().map(|gateway| {
+                (
+                    gateway.mac.clone(),
+                    gateway.summary(),
+                    gateway.passed,
+                    "KEEPALIVE",
+                )
+            }));
+            for (name, summary, passed, kind) in cases {
+                xml.push_str(&format!(
+                    "    <testcase classname=\"rf-tester.SOAK\" name=\"period {}: {}\">\n",
+                    number + 1,
+                    escape(&name)
+                ));
+                if passed {
+                    xml.push_str(&format!(
+                        "      <system-out>{}</system-out>\n",
+                        escape(&summary)
+                    ));
+                } else {
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\" type=\"{}\"/>\n",
+                        escape(&summary),
+                        kind
+                    ));
+                }
+                xml.push_str("    </testcase>\n");
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+}
+
+impl SoakPeriod {
+    /// Sums up the channel `results` of each direction, comparing RSSI with
+    /// the `first` period of the run
+    pub fn new(
+        (start, end): (u64, u64),
+        results: &[(&'static str, ChannelResult)],
+        gateways: Vec<GatewayHealth>,
+        first: Option<&SoakPeriod>,
+        max_per: f64,
+    ) -> SoakPeriod {
+        let mut keys: Vec<(&'static str, &str, &str)> = Vec::new();
+        for (direction, result) in results {
+            let key = (*direction, result.tx_mac.as_str(), result.rx_mac.as_str());
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        let links: Vec<LinkPeriod> = keys
+            .into_iter()
+            .map(|(direction, tx_mac, rx_mac)| {
+                let results: Vec<&ChannelResult> = results
+                    .iter()
+                    .filter(|(other, result)| {
+                        *other == direction && result.tx_mac == tx_mac && result.rx_mac == rx_mac
+                    })
+                    .map(|(_, result)| result)
+                    .collect();
+                let earlier = first.and_then(|first| {
+                    first.links.iter().find(|link| {
+                        link.direction == direction
+                            && link.tx_mac == tx_mac
+                            && link.rx_mac == rx_mac
+                    })
+                });
+                LinkPeriod::new(direction, &results, earlier, max_per)
+            })
+            .collect();
+        let passed =
+            links.iter().all(|link| link.passed) && gateways.iter().all(|gateway| gateway.passed);
+        SoakPeriod {
+            start,
+            end,
+            links,
+            gateways,
+            passed,
+        }
+    }
+
+    /// The period as `number` of the run, which started at `run_start` in
+    /// unix ms
+    pub fn summary(&self, number: usize, run_start: u64) -> String {
+        let mut summary = format!(
+            "Soak period {}, {} s to {} s: {}\n\t{:<6}{:<38}{:<10}{:<10}{:<34}{:<10}RSSI drift",
+            number,
+            self.start.saturating_sub(run_start) / 1000,
+            self.end.saturating_sub(run_start) / 1000,
+            if self.passed { "PASS" } else { "FAIL" },
+            "",
+            "Link",
+            "Result",
+            "PER",
+            "RSSI (mean [min, max] σ)",
+            "SNR"
+        );
+        for link in &self.links {
+            summary.push_str(
+                format!(
+                    "\n\t{:<6}{:<38}{:<10}{:<10}{:<34}{:<10}{}",
+                    link.direction,
+                    format!("{} to {}", link.tx_mac, link.rx_mac),
+                    if link.passed { "PASS" } else { "FAIL" },
+                    format!("{:.1}%", link.per * 100.0),
+                    link.rssi.map(|rssi| rssi.to_string()).unwrap_or_default(),
+                    link.snr
+                        .map(|snr| format!("{:.1}", snr.mean))
+                        .unwrap_or_default(),
+                    link.rssi_drift
+                        .map(|drift| format!("{:+.1} dB", drift))
+                        .unwrap_or_default()
+                )
+                .trim_end(),
+            );
+        }
+        for gateway in &self.gateways {
+            summary.push_str(&format!("\n\t{}", gateway.summary()));
+        }
+        summary
+    }
+
+    /// Appends the period to the rolling report at `path`, one JSON object
+    /// per line
+    pub fn append_to(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        file.write_all(&line)
+    }
+}
+
+impl LinkPeriod {
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} of {} received, PER {:.1}%",
+            self.received,
+            self.sent,
+            self.per * 100.0
+        );
+        if let Some(rssi) = self.rssi {
+            summary.push_str(&format!(", RSSI {}", rssi));
+        }
+        if let Some(drift) = self.rssi_drift {
+            summary.push_str(&format!(", drift {:+.1} dB", drift));
+        }
+        summary
+    }
+
+    fn new(
+        direction: &'static str,
+        results: &[&ChannelResult],
+        first: Option<&LinkPeriod>,
+        max_per: f64,
+    ) -> LinkPeriod {
+        let packets = results
+            .iter()
+            .map(|result| result.packets.len())
+            .sum::<usize>();
+        let sent = results.iter().map(|result| result.sent).sum();
+        let received = results.iter().map(|result| result.received).sum();
+        let samples = |sample: fn(&PacketResult) -> Option<f64>| -> Vec<f64> {
+            results
+                .iter()
+                .flat_map(|result| result.packets.iter())
+                .filter_map(sample)
+                .collect()
+        };
+        let rssi = Stats::from_samples(&samples(|packet| packet.rssi.map(f64::from)));
+        let snr = Stats::from_samples(&samples(|packet| packet.snr.map(f64::from)));
+        let per = if packets == 0 {
+            1.0
+        } else {
+            1.0 - received as f64 / packets as f64
+        };
+        let rssi_drift = match (rssi, first.and_then(|first| first.rssi)) {
+            (Some(rssi), Some(first)) => Some(rssi.mean - first.mean),
+            _ => None,
+        };
+        LinkPeriod {
+            direction,
+            tx_mac: results[0].tx_mac.clone(),
+            rx_mac: results[0].rx_mac.clone(),
+            sent,
+            received,
+            per,
+            rssi,
+            snr,
+            rssi_drift,
+            passed: received > 0 && per <= max_per,
+        }
+    }
+}
@@ -0,0 +1,1441 @@
+use super::{
+    gps,
+    health::{GatewayStat, Monitor, StatCheck},
+    opt::Opt,
+    payload::{self, Payload},
+    power_sweep::PowerSweep,
+    record::{self, Recorder},
+    report::{
+        self, ChannelResult, CrcStatus, DirectionReport, GatewayReport, PacketResult, Report,
+        Verdict,
+    },
+    soak::{Soak, SoakPeriod},
+    tx_error::{TxError, TxPolicy},
+    udp::{self, Downlinks},
+    wire::{self, Downlink},
+};
+use regions::{Beacon, DataRate, BEACON_PERIOD};
+use semtech_udp::{pull_resp, push_data::RxPk, server_runtime::Event, MacAddress, StringOrNum, Up};
+use std::{fmt, str::FromStr, sync::Arc};
+use tokio::time::{Duration, Instant};
+use tokio::{
+    sync::mpsc,
+    time::{delay_for, timeout},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Role {
+    Tested,
+    Control,
+}
+
+/// A packet heard by the gateway `MacAddress`, with the frequency offset its
+/// demodulator measured (foff) if it reported one
+pub type Message = (RxPk, Option<i64>, MacAddress, Role);
+
+/// A server gateways connect to, and the recording of what goes through it
+pub struct Server {
+    port: u16,
+    downlinks: Arc<Downlinks>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+/// One direction of the test: packets are sent through `server` to the
+/// gateway `tx_mac` and expected back from `rx_mac`, a gateway of
+/// `receiver_role`. The downlinks are counted in `monitor`
+pub struct Link<'a> {
+    receiver_role: Role,
+    server: &'a Server,
+    tx_mac: MacAddress,
+    rx_mac: MacAddress,
+    monitor: &'a Monitor,
+}
+
+/// Which gateways a server lets in, and in which role. Listed EUIs decide
+/// the role whatever the port; other gateways are ignored, except for the
+/// first one to connect to a port reserved to a role with no EUIs listed
+pub(crate) struct Admission {
+    port: u16,
+    tested: Vec<MacAddress>,
+    control: Vec<MacAddress>,
+    first: Option<Role>,
+    admitted: Option<(MacAddress, Role)>,
+}
+
+impl Admission {
+    pub(crate) fn new(cli: &Opt, port: u16) -> Admission {
+        let macs = |macs: &[u64]| -> Vec<MacAddress> {
+            macs.iter()
+                .map(|mac| MacAddress::new(&mac.to_be_bytes()))
+                .collect()
+        };
+        let first = match (
+            cli.test_ports.contains(&port) && cli.test_mac.is_empty(),
+            cli.control_ports.contains(&port) && cli.control_mac.is_empty(),
+        ) {
+            (true, false) => Some(Role::Tested),
+            (false, true) => Some(Role::Control),
+            // sharing a port needs EUIs for both roles, see check_gateways
+            _ => None,
+        };
+        Admission {
+            port,
+            tested: macs(&cli.test_mac),
+            control: macs(&cli.control_mac),
+            first,
+            admitted: None,
+        }
+    }
+
+    pub(crate) fn role(&self, mac: &MacAddress) -> Option<Role> {
+        if self.tested.contains(mac) {
+            Some(Role::Tested)
+        } else if self.control.contains(mac) {
+            Some(Role::Control)
+        } else {
+            match &self.admitted {
+                Some((admitted, role)) if admitted == mac => Some(role.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    // the role of a gateway which just connected, if it is let in
+    fn admit(&mut self, mac: &MacAddress) -> Option<Role> {
+        if let Some(role) = self.role(mac) {
+            return Some(role);
+        }
+        let role = self.first.take()?;
+        self.admitted = Some((*mac, role.clone()));
+        Some(role)
+    }
+}
+
+/// A gateway let in by a server, which connected to `port`
+pub(crate) type Connection = (MacAddress, Role, u16);
+
+/// Handles the events of the server on `port`: lets gateways in, hands
+/// their packets over and keeps track of their stats
+pub(crate) struct Listener {
+    pub(crate) admission: Admission,
+    pub(crate) sender: mpsc::Sender<Message>,
+    pub(crate) connections: mpsc::Sender<Connection>,
+    pub(crate) monitor: Arc<Monitor>,
+    pub(crate) recorder: Option<Arc<Recorder>>,
+    pub(crate) debug: bool,
+}
+
+impl Listener {
+    /// Handles the `events` raised by the datagram `frame`
+    pub(crate) async fn handle(&mut self, frame: &[u8], events: Vec<Event>) {
+        if let Some(recorder) = &self.recorder {
+            recorder.up(self.admission.port, frame);
+        }
+        for event in events {
+            self.handle_event(frame, event).await;
+        }
+    }
+
+    async fn handle_event(&mut self, frame: &[u8], event: Event) {
+        let port = self.admission.port;
+        match event {
+            Event::UnableToParseUdpFrame(buf) => match wire::parse_push_data(&buf) {
+                Some((mac, packets, stat)) => {
+                    if let Some(role) = self.admission.role(&mac) {
+                        if let Some(stat) = stat {
+                            self.monitor.record_stat(&mac, stat);
+                        }
+                        for rxpk in packets {
+                            let foff = wire::foff(frame, &rxpk);
+                            let message = (rxpk, foff, mac, role.clone());
+                            self.sender.send(message).await.unwrap();
+                        }
+                    }
+                }
+                None => {
+                    println!("Semtech UDP Parsing Error");
+                    println!("UDP data: {:?}", buf);
+                }
+            },
+            Event::NewClient((mac, addr)) => match self.admission.admit(&mac) {
+                Some(role) => {
+                    println!("New packet forwarder client: {}, {}", mac, addr);
+                    // its PULL_DATA came before it was admitted
+                    self.monitor.record_keepalive(&mac);
+                    // run stops listening once everyone is in
+                    let _ = self.connections.send((mac, role, port)).await;
+                }
+                None => println!(
+                    "Ignoring packet forwarder client {} on port {}: not an expected gateway",
+                    report::mac_to_string(&mac),
+                    port
+                ),
+            },
+            Event::UpdateClient((mac, addr)) => {
+                if self.admission.role(&mac).is_some() {
+                    println!("Mac existed, but IP updated: {}, {}", mac, addr);
+                    self.monitor.record_reconnect(&mac);
+                }
+            }
+            Event::PacketReceived(rxpk, mac) => {
+                if let Some(role) = self.admission.role(&mac) {
+                    let foff = wire::foff(frame, &rxpk);
+                    self.sender.send((rxpk, foff, mac, role)).await.unwrap();
+                }
+            }
+            Event::NoClientWithMac(_packet, mac) => {
+                println!("Tried to send to client with unknown MAC: {:?}", mac)
+            }
+            Event::RawPacket(packet) => {
+                if self.debug {
+                    println!("{}: {:?}", port, packet);
+                }
+                match &packet {
+                    Up::PushData(push_data) => {
+                        let mac = push_data.gateway_mac;
+                        let stat = push_data.data.stat.as_ref();
+                        if let (Some(stat), Some(_)) = (stat, self.admission.role(&mac)) {
+                            if let Some(stat) = GatewayStat::from_push_data(stat) {
+                                self.monitor.record_stat(&mac, stat);
+                            }
+                        }
+                    }
+                    Up::PullData(pull_data) => {
+                        if self.admission.role(&pull_data.gateway_mac).is_some() {
+                            self.monitor.record_keepalive(&pull_data.gateway_mac);
+                        }
+                    }
+                    Up::TxAck(_) => (),
+                }
+            }
+        }
+    }
+}
+
+async fn start_server(mut listener: Listener) -> Result<Server, Box<dyn std::error::Error>> {
+    let port = listener.admission.port;
+    println!("Starting server: 0.0.0.0:{}", port);
+    let (mut uplinks, downlinks) = udp::bind(port).await?;
+    let recorder = listener.recorder.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (frame, events) = uplinks.recv().await;
+            listener.handle(&frame, events).await;
+        }
+    });
+
+    Ok(Server {
+        port,
+        downlinks,
+        recorder,
+    })
+}
+
+/// Runs the whole test: waits for every gateway to connect, then exercises
+/// the TX and RX directions of each gateway under test against each control
+/// gateway, as configured by `cli`. Gateways are tested one after the
+/// other: a packet heard is matched against the single channel under test,
+/// so several gateways cannot share the air on different frequencies yet
+pub async fn run(cli: &Opt) -> Result<Report, Box<dyn std::error::Error>> {
+    cli.check_size()?;
+    cli.check_fsk()?;
+    cli.check_thresholds()?;
+    cli.check_gateways()?;
+    let (packet_tx, mut packet_rx): (mpsc::Sender<Message>, mpsc::Receiver<Message>) =
+        mpsc::channel(120);
+    let (connection_tx, mut connection_rx) = mpsc::channel::<Connection>(16);
+    let max_keepalive_gap = Duration::from_secs(cli.max_keepalive_gap);
+    let monitor = Arc::new(Monitor::new(max_keepalive_gap));
+    let recorder = match &cli.record {
+        Some(path) => Some(Arc::new(Recorder::create(path)?)),
+        None => None,
+    };
+
+    let mut servers = Vec::new();
+    for port in cli.ports() {
+        let listener = Listener {
+            admission: Admission::new(cli, port),
+            sender: packet_tx.clone(),
+            connections: connection_tx.clone(),
+            monitor: monitor.clone(),
+            recorder: recorder.clone(),
+            debug: cli.debug,
+        };
+        servers.push(start_server(listener).await?);
+    }
+    let server = |port: u16| {
+        servers
+            .iter()
+            .find(|server| server.port == port)
+            .expect("every port has a server")
+    };
+
+    println!("Blocking until all clients connect");
+    let (test_count, control_count) = cli.gateway_counts();
+    let (mut tested, mut controls) = (Vec::new(), Vec::new());
+    while tested.len() < test_count || controls.len() < control_count {
+        let (mac, role, port) = connection_rx
+            .recv()
+            .await
+            .expect("Channels should never close");
+        let gateways = match role {
+            Role::Tested => &mut tested,
+            Role::Control => &mut controls,
+        };
+        if !gateways.iter().any(|(connected, _)| *connected == mac) {
+            gateways.push((mac, port));
+        }
+    }
+    // in the order they were given on the command line
+    tested.sort_by_key(|(mac, port)| cli.gateway_order(Role::Tested, mac, *port));
+    controls.sort_by_key(|(mac, port)| cli.gateway_order(Role::Control, mac, *port));
+
+    let mut tagger = Tagger::new();
+    if let Some(duration) = cli.soak {
+        let pairs: Vec<Pair> = tested
+            .iter()
+            .flat_map(|(test_mac, test_port)| {
+                controls.iter().map(move |(control_mac, control_port)| {
+                    (
+                        (*test_mac, server(*test_port)),
+                        (*control_mac, server(*control_port)),
+                    )
+                })
+            })
+            .collect();
+        let soak = run_soak(cli, duration, &pairs, &mut packet_rx, &mut tagger, &monitor).await?;
+        return Ok(Report {
+            region: format!("{:?}", cli.region),
+            gateways: Vec::new(),
+            stats: Vec::new(),
+            soak: Some(soak),
+        });
+    }
+
+    let mut gateways = Vec::new();
+    for (test_mac, test_port) in &tested {
+        let mac = report::mac_to_string(test_mac);
+        println!("Testing gateway {}", mac);
+        let mut directions = Vec::new();
+        let mut power_sweep = None;
+        for (index, (control_mac, control_port)) in controls.iter().enumerate() {
+            if control_count > 1 {
+                println!(
+                    "Against control gateway {}",
+                    report::mac_to_string(control_mac)
+                );
+            }
+            // the power sweep compares RSSI along a single receiver
+            let (pair, sweep) = run_pair(
+                cli,
+                (*test_mac, server(*test_port)),
+                (*control_mac, server(*control_port)),
+                &mut packet_rx,
+                &mut tagger,
+                &monitor,
+                index == 0,
+            )
+            .await?;
+            directions.extend(pair);
+            power_sweep = power_sweep.or(sweep);
+        }
+
+        let gateway = GatewayReport {
+            mac,
+            directions,
+            power_sweep,
+        };
+        print_results(cli, &gateway);
+        gateways.push(gateway);
+    }
+
+    let mut stats = Vec::new();
+    if cli.check_stats {
+        let macs: Vec<MacAddress> = tested
+            .iter()
+            .chain(controls.iter())
+            .map(|(mac, _)| *mac)
+            .collect();
+        stats = check_stats(cli, &monitor, &macs).await;
+        print_stat_checks(&stats);
+    }
+
+    let report = Report {
+        region: format!("{:?}", cli.region),
+        gateways,
+        stats,
+        soak: None,
+    };
+    Ok(report)
+}
+
+pub(crate) fn print_results(cli: &Opt, gateway: &GatewayReport) {
+    println!("Results for gateway {}", gateway.mac);
+    for direction in &gateway.directions {
+        if direction.direction == "PWR" {
+            continue;
+        }
+        if cli.sweep {
+            println!("{}", direction.matrix());
+        } else {
+            println!("{}", direction.summary());
+        }
+    }
+    if let Some(power_sweep) = &gateway.power_sweep {
+        println!("{}", power_sweep.summary());
+    }
+}
+
+pub(crate) fn print_stat_checks(stats: &[StatCheck]) {
+    println!("Stat counters");
+    for check in stats {
+        println!("\t{}", check.summary());
+    }
+}
+
+/// Waits for every gateway to report its stats past the last downlink, a
+/// stat interval at most, then checks their counters
+async fn check_stats(cli: &Opt, monitor: &Monitor, macs: &[MacAddress]) -> Vec<StatCheck> {
+    println!("Waiting for the stat reports of the gateways");
+    let since = report::now_ms();
+    let deadline = Instant::now() + Duration::from_secs(cli.stat_interval + 5);
+    for mac in macs {
+        while monitor.last_stat_at(mac).is_none_or(|at| at < since) && Instant::now() < deadline {
+            delay_for(Duration::from_millis(100)).await;
+        }
+    }
+    macs.iter().map(|mac| monitor.check(mac)).collect()
+}
+
+/// A gateway under test and a control gateway, with their servers
+type Pair<'a> = ((MacAddress, &'a Server), (MacAddress, &'a Server));
+
+/// Repeats the TX and RX directions of every pair of gateways until
+/// `duration` is up, summing them up period after period. A period ends
+/// with the round running when it is up
+async fn run_soak(
+    cli: &Opt,
+    duration: Duration,
+    pairs: &[Pair<'_>],
+    packet_rx: &mut mpsc::Receiver<Message>,
+    tagger: &mut Tagger,
+    monitor: &Monitor,
+) -> Result<Soak, Box<dyn std::error::Error>> {
+    let steps = cli.steps();
+    let mut macs: Vec<MacAddress> = Vec::new();
+    for ((test_mac, _), (control_mac, _)) in pairs {
+        for mac in [test_mac, control_mac] {
+            if !macs.contains(mac) {
+                macs.push(*mac);
+            }
+        }
+    }
+    // what happened while the gateways connected is not part of the run
+    for mac in &macs {
+        monitor.take_health(mac);
+    }
+
+    let mut soak = Soak {
+        duration: duration.as_secs(),
+        period: cli.soak_period.as_secs(),
+        periods: Vec::new(),
+    };
+    let start = (Instant::now(), report::now_ms());
+    let mut period_start = start;
+    let mut results: Vec<(&'static str, ChannelResult)> = Vec::new();
+    let mut round = 0;
+    while start.0.elapsed() < duration {
+        round += 1;
+        println!("Soak round {}, {} s in", round, start.0.elapsed().as_secs());
+        for ((test_mac, test_server), (control_mac, control_server)) in pairs {
+            let mut links = [
+                (
+                    "TX",
+                    Link {
+                        receiver_role: Role::Control,
+                        server: test_server,
+                        tx_mac: *test_mac,
+                        rx_mac: *control_mac,
+                        monitor,
+                    },
+                ),
+                (
+                    "RX",
+                    Link {
+                        receiver_role: Role::Tested,
+                        server: control_server,
+                        tx_mac: *control_mac,
+                        rx_mac: *test_mac,
+                        monitor,
+                    },
+                ),
+            ];
+            for (direction, link) in links.iter_mut() {
+                for step in &steps {
+                    let channels = run_test(link, direction, cli, packet_rx, tagger, step).await?;
+                    results.extend(channels.into_iter().map(|result| (*direction, result)));
+                }
+            }
+        }
+
+        if period_start.0.elapsed() >= cli.soak_period || start.0.elapsed() >= duration {
+            let end = report::now_ms();
+            let health = macs.iter().map(|mac| monitor.take_health(mac)).collect();
+            let period = SoakPeriod::new(
+                (period_start.1, end),
+                &results,
+                health,
+                soak.periods.first(),
+                cli.max_per,
+            );
+            println!("{}", period.summary(soak.periods.len() + 1, start.1));
+            if let Some(path) = &cli.soak_report {
+                period.append_to(path)?;
+            }
+            soak.periods.push(period);
+            results.clear();
+            period_start = (Instant::now(), end);
+        }
+    }
+    Ok(soak)
+}
+
+/// Tests a gateway under test against a control gateway, and sweeps its
+/// power if asked to and configured
+async fn run_pair(
+    cli: &Opt,
+    (test_mac, test_server): (MacAddress, &Server),
+    (control_mac, control_server): (MacAddress, &Server),
+    packet_rx: &mut mpsc::Receiver<Message>,
+    tagger: &mut Tagger,
+    monitor: &Monitor,
+    sweep_power: bool,
+) -> Result<(Vec<DirectionReport>, Option<PowerSweep>), Box<dyn std::error::Error>> {
+    let steps = cli.steps();
+
+    let mut links = [
+        (
+            "TX",
+            "Testing ability of Test Gateway to Transmit on Uplink Channels",
+            Link {
+                receiver_role: Role::Control,
+                server: test_server,
+                tx_mac: test_mac,
+                rx_mac: control_mac,
+                monitor,
+            },
+        ),
+        (
+            "RX",
+            "Testing ability of Test Gateway to Receive on Uplink Channels",
+            Link {
+                receiver_role: Role::Tested,
+                server: control_server,
+                tx_mac: control_mac,
+                rx_mac: test_mac,
+                monitor,
+            },
+        ),
+    ];
+    let mut directions = Vec::new();
+    for (direction, description, link) in links.iter_mut() {
+        println!("{}", description);
+        let mut channels = Vec::new();
+        for step in &steps {
+            if steps.len() > 1 {
+                println!("\tData rate {}", step.datr);
+            }
+            channels.extend(run_test(link, direction, cli, packet_rx, tagger, step).await?);
+        }
+        directions.push(DirectionReport {
+            direction,
+            channels,
+        });
+    }
+
+    if let Some(window) = cli.schedule {
+        println!(
+            "Testing ability of Test Gateway to transmit in {}, {} s after an uplink",
+            window,
+            window.delay().as_secs()
+        );
+        let [(.., tested), (.., control)] = &mut links;
+        let mut channels = Vec::new();
+        for step in &steps {
+            if steps.len() > 1 {
+                println!("\tData rate {}", step.datr);
+            }
+            channels.extend(
+                run_scheduled(tested, control, cli, packet_rx, tagger, step, window).await?,
+            );
+        }
+        directions.push(DirectionReport {
+            direction: "SCHED",
+            channels,
+        });
+    }
+
+    if cli.gps {
+        println!("Testing ability of Test Gateway to transmit at GPS times");
+        let (_, _, link) = &mut links[0];
+        let mut channels = Vec::new();
+        for step in &steps {
+            if steps.len() > 1 {
+                println!("\tData rate {}", step.datr);
+            }
+            channels.extend(run_gps(link, cli, packet_rx, tagger, step).await?);
+        }
+        directions.push(DirectionReport {
+            direction: "GPS",
+            channels,
+        });
+    }
+
+    if cli.beacon {
+        println!("Testing ability of Test Gateway to send a Class B beacon");
+        let (_, _, link) = &mut links[0];
+        let channels = vec![run_beacon(link, cli, packet_rx).await];
+        directions.push(DirectionReport {
+            direction: "BCN",
+            channels,
+        });
+    }
+
+    if cli.inverted_iq {
+        println!("Testing that neither gateway hears packets sent with inverted IQ");
+        // the normal IQ packets must be heard for the silence to count
+        let control = cli.first_channel_step(cli.power, false, true);
+        let step = cli.first_channel_step(cli.power, true, true);
+        let mut channels = Vec::new();
+        for (.., link) in links.iter_mut() {
+            channels.extend(run_test(link, "IQ", cli, packet_rx, tagger, &control).await?);
+            channels.extend(run_test(link, "IQ", cli, packet_rx, tagger, &step).await?);
+        }
+        let mut iq = DirectionReport {
+            direction: "IQ",
+            channels,
+        };
+        iq.check_iq_controls();
+        directions.push(iq);
+    }
+
+    if cli.no_crc {
+        println!("Testing ability of Test Gateway to transmit without CRC");
+        let (_, _, link) = &mut links[0];
+        let step = cli.first_channel_step(cli.power, false, false);
+        let channels = run_test(link, "CRC", cli, packet_rx, tagger, &step).await?;
+        directions.push(DirectionReport {
+            direction: "CRC",
+            channels,
+        });
+    }
+
+    let power_steps = cli.power_steps()?;
+    let mut power_sweep = None;
+    if sweep_power && !power_steps.is_empty() {
+        println!("Sweeping transmit power of Test Gateway");
+        let (_, _, link) = &mut links[0];
+        let mut channels = Vec::new();
+        for step in &power_steps {
+            println!("\tPower {} dBm", step.power);
+            channels.extend(run_test(link, "PWR", cli, packet_rx, tagger, step).await?);
+        }
+        power_sweep = Some(PowerSweep::from_results(
+            &channels,
+            cli.max_power_deviation,
+            cli.max_power_slope_error,
+        ));
+        directions.push(DirectionReport {
+            direction: "PWR",
+            channels,
+        });
+    }
+
+    Ok((directions, power_sweep))
+}
+
+/// Hands out the payload of every packet of the run. Sequence numbers keep
+/// increasing across channels and data rates so that a late packet is never
+/// mistaken for a current one
+struct Tagger {
+    nonce: u32,
+    next_sequence: u32,
+}
+
+impl Tagger {
+    fn new() -> Tagger {
+        Tagger {
+            nonce: payload::run_nonce(),
+            next_sequence: 0,
+        }
+    }
+
+    fn next(&mut self, channel: usize) -> Payload {
+        let payload = Payload {
+            nonce: self.nonce,
+            sequence: self.next_sequence,
+            channel: channel as u16,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        payload
+    }
+}
+
+/// LoRaWAN receive windows, opening 1 s and 2 s after an uplink
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RxWindow {
+    Rx1,
+    Rx2,
+}
+
+impl RxWindow {
+    pub fn delay(&self) -> Duration {
+        match self {
+            RxWindow::Rx1 => Duration::from_secs(1),
+            RxWindow::Rx2 => Duration::from_secs(2),
+        }
+    }
+}
+
+impl fmt::Display for RxWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RxWindow::Rx1 => f.pad("RX1"),
+            RxWindow::Rx2 => f.pad("RX2"),
+        }
+    }
+}
+
+impl FromStr for RxWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RxWindow, String> {
+        match s.to_lowercase().as_str() {
+            "rx1" => Ok(RxWindow::Rx1),
+            "rx2" => Ok(RxWindow::Rx2),
+            _ => Err(format!("unknown receive window: {}", s)),
+        }
+    }
+}
+
+/// A data rate and power to test and the channels to test them on
+pub struct Step {
+    pub datr: String,
+    pub power: u64,
+    pub channels: Vec<usize>,
+    /// send with inverted IQ, as downlinks are. Gateways listen for uplinks
+    /// with normal polarity, so they should not hear such packets
+    pub ipol: bool,
+    /// send with a CRC, which uplinks always have
+    pub crc: bool,
+}
+
+async fn run_test(
+    link: &mut Link<'_>,
+    test: &'static str,
+    cli_options: &Opt,
+    receiver: &mut mpsc::Receiver<Message>,
+    tagger: &mut Tagger,
+    step: &Step,
+) -> Result<Vec<ChannelResult>, Box<dyn std::error::Error>> {
+    let Step {
+        datr,
+        power,
+        channels,
+        ..
+    } = step;
+    let power = *power;
+    let size = cli_options.size_for(datr);
+    let airtime = time_on_air(datr, size);
+    let interval = Duration::from_millis(cli_options.interval);
+    let mut results = Vec::new();
+
+    for (index, channel) in channels.iter().enumerate() {
+        println!(
+            "\tDispatching {} packet(s) on channel ({:?} {}: {} MHz)",
+            cli_options.count,
+            cli_options.region,
+            index + 1,
+            channel
+        );
+
+        let mut result = channel_result(&link.tx_mac, &link.rx_mac, index, *channel, datr, power);
+        result.ipol = step.ipol;
+        result.crc = step.crc;
+
+        for n in 0..cli_options.count {
+            let payload = tagger.next(index);
+            let mut txpk = create_packet(
+                channel,
+                datr,
+                power,
+                payload.encode(size),
+                At::Now,
+                cli_options.fdev,
+            );
+            txpk.ipol = step.ipol;
+            if !step.crc {
+                txpk.ncrc = Some(true);
+            }
+            let mut packet = packet_result(payload.sequence);
+
+            let purpose = (test, record::Step::Packet);
+            match dispatch(link, txpk.into(), purpose, cli_options).await {
+                Ok(()) => packet.sent_at = Some(report::now_ms()),
+                Err(error) => packet.tx_error = Some(error),
+            }
+            let skip = packet
+                .tx_error
+                .is_some_and(|error| give_up(link, *channel, error, cli_options));
+            result.packets.push(packet);
+            if skip {
+                result.skipped = cli_options.count - n - 1;
+                break;
+            }
+
+            if n + 1 < cli_options.count {
+                // keep collecting while spacing out the transmissions
+                let until = Instant::now() + interval;
+                receive(receiver, link, tagger.nonce, &mut result, until, false).await;
+            }
+        }
+
+        // the last packet is heard once it has been sent in full
+        let until = Instant::now() + airtime + Duration::from_millis(cli_options.timeout);
+        receive(receiver, link, tagger.nonce, &mut result, until, true).await;
+        result.finish(&cli_options.thresholds(result.power));
+        println!("\t{}: {}", result.verdict, result.statistics());
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Has the control gateway send an uplink, which the tested gateway answers
+/// in `window`, scheduled against the `tmst` at which it heard the uplink.
+/// The control gateway's counter is unrelated to the tested gateway's, so
+/// the timing of the answer is measured on the host, from the arrival of the
+/// uplink to that of the answer
+#[allow(clippy::too_many_arguments)]
+async fn run_scheduled(
+    tested: &mut Link<'_>,
+    control: &mut Link<'_>,
+    cli_options: &Opt,
+    receiver: &mut mpsc::Receiver<Message>,
+    tagger: &mut Tagger,
+    step: &Step,
+    window: RxWindow,
+) -> Result<Vec<ChannelResult>, Box<dyn std::error::Error>> {
+    let Step {
+        datr,
+        power,
+        channels,
+        ..
+    } = step;
+    let power = *power;
+    let size = cli_options.size_for(datr);
+    let delay = window.delay();
+    let mut results = Vec::new();
+
+    for (index, channel) in channels.iter().enumerate() {
+        println!(
+            "\tScheduling {} packet(s) in {} on channel ({:?} {}: {} MHz)",
+            cli_options.count,
+            window,
+            cli_options.region,
+            index + 1,
+            channel
+        );
+        let mut result =
+            channel_result(&tested.tx_mac, &tested.rx_mac, index, *channel, datr, power);
+
+        for n in 0..cli_options.count {
+            let uplink = tagger.next(index);
+            let data = uplink.encode(size);
+            // both packets are heard once they have been sent in full
+            let airtime = time_on_air(datr, data.len());
+            let txpk = create_packet(channel, datr, power, data, At::Now, cli_options.fdev);
+            let purpose = ("SCHED", record::Step::Uplink);
+            if let Err(error) = dispatch(control, txpk.into(), purpose, cli_options).await {
+                println!("\tControl gateway did not transmit the uplink: {}", error);
+                result.skipped += 1;
+                continue;
+            }
+            let until = Instant::now() + airtime + Duration::from_millis(cli_options.timeout);
+            let nonce = tagger.nonce;
+            let heard = hear(receiver, control, until, |data| {
+                Payload::decode(data).is_ok_and(|payload| {
+                    payload.nonce == nonce && payload.sequence == uplink.sequence
+                })
+            });
+            let (tmst, heard_at) = match heard.await {
+                Some((rxpk, _, heard_at)) => (*rxpk.get_timestamp(), heard_at),
+                None => {
+                    println!("\tTest gateway did not hear the uplink");
+                    result.skipped += 1;
+                    continue;
+                }
+            };
+
+            let downlink = tagger.next(index);
+            // the counter is 32 bits and wraps around every ~71 minutes
+            let tmst = (tmst as u32).wrapping_add(delay.as_micros() as u32);
+            let txpk = create_packet(
+                channel,
+                datr,
+                power,
+                downlink.encode(size),
+                At::Counter(tmst),
+                cli_options.fdev,
+            );
+            let mut packet = packet_result(downlink.sequence);
+            let purpose = ("SCHED", record::Step::Packet);
+            match dispatch(tested, txpk.into(), purpose, cli_options).await {
+                Ok(()) => packet.sent_at = Some(report::now_ms()),
+                Err(error) => packet.tx_error = Some(error),
+            }
+            let skip = packet
+                .tx_error
+                .is_some_and(|error| give_up(tested, *channel, error, cli_options));
+            result.packets.push(packet);
+            if skip {
+                result.skipped += cli_options.count - n - 1;
+                break;
+            }
+
+            let until =
+                Instant::now() + delay + airtime + Duration::from_millis(cli_options.timeout);
+            receive(receiver, tested, tagger.nonce, &mut result, until, true).await;
+            if let Some(packet) = result.packets.last_mut() {
+                if let Some(received_at) = packet.received_at {
+                    let timing_error =
+                        received_at as i64 - heard_at as i64 - (delay + airtime).as_millis() as i64;
+                    println!(
+                        "\tAnswer landed {:+} ms from the {} delay",
+                        timing_error, window
+                    );
+                    packet.timing_error = Some(timing_error);
+                }
+            }
+        }
+
+        finish_timed(&mut result, cli_options);
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Has the tested gateway transmit at GPS times, `tmms`, on whole seconds
+/// at least a second ahead. The control gateway should hear each packet
+/// once it has been sent in full, as measured against the host clock
+async fn run_gps(
+    link: &mut Link<'_>,
+    cli_options: &Opt,
+    receiver: &mut mpsc::Receiver<Message>,
+    tagger: &mut Tagger,
+    step: &Step,
+) -> Result<Vec<ChannelResult>, Box<dyn std::error::Error>> {
+    let Step {
+        datr,
+        power,
+        channels,
+        ..
+    } = step;
+    let power = *power;
+    let size = cli_options.size_for(datr);
+    let mut results = Vec::new();
+
+    for (index, channel) in channels.iter().enumerate() {
+        println!(
+            "\tScheduling {} packet(s) at GPS times on channel ({:?} {}: {} MHz)",
+            cli_options.count,
+            cli_options.region,
+            index + 1,
+            channel
+        );
+        let mut result = channel_result(&link.tx_mac, &link.rx_mac, index, *channel, datr, power);
+
+        for n in 0..cli_options.count {
+            let payload = tagger.next(index);
+            let data = payload.encode(size);
+            let airtime = time_on_air(datr, data.len());
+            let now = gps::gps_ms(report::now_ms());
+            let tmms = (now / 1000 + 2) * 1000;
+            let txpk = create_packet(channel, datr, power, data, At::Gps(tmms), cli_options.fdev);
+            let mut packet = packet_result(payload.sequence);
+            let purpose = ("GPS", record::Step::Packet);
+            match dispatch(link, txpk.into(), purpose, cli_options).await {
+                Ok(()) => packet.sent_at = Some(report::now_ms()),
+                Err(error) => packet.tx_error = Some(error),
+            }
+            let skip = packet
+                .tx_error
+                .is_some_and(|error| give_up(link, *channel, error, cli_options));
+            result.packets.push(packet);
+            if skip {
+                result.skipped = cli_options.count - n - 1;
+                break;
+            }
+
+            let until = Instant::now()
+                + Duration::from_millis(tmms - now)
+                + airtime
+                + Duration::from_millis(cli_options.timeout);
+            receive(receiver, link, tagger.nonce, &mut result, until, true).await;
+            if let Some(packet) = result.packets.last_mut() {
+                if let Some(received_at) = packet.received_at {
+                    let expected = gps::unix_ms(tmms) + airtime.as_millis() as u64;
+                    let timing_error = received_at as i64 - expected as i64;
+                    println!(
+                        "\tPacket landed {:+} ms from GPS time {} ms",
+                        timing_error, tmms
+                    );
+                    packet.timing_error = Some(timing_error);
+                }
+            }
+        }
+
+        finish_timed(&mut result, cli_options);
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Has the tested gateway send a Class B beacon in the next beacon slot
+/// that is at least a couple of seconds away, and checks that the control
+/// gateway hears it on time, on the region's beacon channel. Like any
+/// beacon, it goes with an implicit header, no CRC and a 10 symbol preamble
+async fn run_beacon(
+    link: &mut Link<'_>,
+    cli_options: &Opt,
+    receiver: &mut mpsc::Receiver<Message>,
+) -> ChannelResult {
+    let period = BEACON_PERIOD as u64 * 1000;
+    let now = gps::gps_ms(report::now_ms());
+    let offset = cli_options.beacon_offset % period;
+    let mut slot = ((now - offset) / period + 1) * period + offset;
+    if slot - now < 2000 {
+        slot += period;
+    }
+    let time = (slot / 1000) as u32;
+    let beacon = cli_options.region.get_beacon(time);
+    let datr = beacon.data_rate.to_string();
+    let data = gps::beacon_payload(&beacon, time);
+    let airtime = beacon.time_on_air();
+    println!(
+        "\tBeacon at GPS time {} s on {} MHz, {}, in {} s",
+        time,
+        beacon.frequency as f64 / 1_000_000.0,
+        datr,
+        (slot - now) / 1000
+    );
+
+    let mut result = channel_result(
+        &link.tx_mac,
+        &link.rx_mac,
+        0,
+        beacon.frequency,
+        &datr,
+        cli_options.power,
+    );
+    result.crc = false;
+    let mut packet = packet_result(0);
+    let mut txpk = create_packet(
+        &beacon.frequency,
+        &datr,
+        cli_options.power,
+        data.clone(),
+        At::Gps(slot),
+        cli_options.fdev,
+    );
+    txpk.prea = Some(Beacon::PREAMBLE as u64);
+    txpk.ncrc = Some(true);
+    let downlink = Downlink {
+        implicit_header: true,
+        ..txpk.into()
+    };
+    match dispatch(link, downlink, ("BCN", record::Step::Packet), cli_options).await {
+        Ok(()) => packet.sent_at = Some(report::now_ms()),
+        Err(error) => {
+            give_up(link, beacon.frequency, error, cli_options);
+            packet.tx_error = Some(error);
+        }
+    }
+
+    if packet.sent_at.is_some() {
+        let until = Instant::now()
+            + Duration::from_millis(slot - now)
+            + airtime
+            + Duration::from_millis(cli_options.timeout);
+        let heard = hear(receiver, link, until, |heard| heard == data).await;
+        if let Some((rxpk, foff, heard_at)) = heard {
+            let freq = beacon.frequency as f64 / 1_000_000.0;
+            if rxpk.get_datarate() == datr && (rxpk.get_frequency() - freq).abs() < 0.1 {
+                let expected = gps::unix_ms(slot) + airtime.as_millis() as u64;
+                let timing_error = heard_at as i64 - expected as i64;
+                println!(
+                    "\tReceived beacon! RSSI = {}, SNR = {}, {:+} ms from the slot",
+                    rxpk.get_rssi(),
+                    rxpk.get_snr(),
+                    timing_error
+                );
+                packet.received_at = Some(heard_at);
+                packet.rssi = Some(rxpk.get_rssi());
+                packet.snr = Some(rxpk.get_snr());
+                packet.frequency_offset =
+                    Some(((rxpk.get_frequency() - freq) * 1_000_000.0).round() as i64);
+                packet.foff = foff;
+                packet.timing_error = Some(timing_error);
+            } else {
+                println!(
+                    "\tReceived beacon on wrong channel or data rate: {} MHz, {}",
+                    rxpk.get_frequency(),
+                    rxpk.get_datarate()
+                );
+                result.mismatched += 1;
+            }
+        }
+    }
+    result.packets.push(packet);
+    finish_timed(&mut result, cli_options);
+    result
+}
+
+/// Finishes the result of timed transmissions, which also fail when they
+/// landed further than the tolerance from when they were scheduled
+fn finish_timed(result: &mut ChannelResult, cli_options: &Opt) {
+    result.finish(&cli_options.thresholds(result.power));
+    let tolerance = cli_options.schedule_tolerance;
+    if result.verdict == Verdict::Pass
+        && result.packets.iter().any(|packet| {
+            packet
+                .timing_error
+                .is_some_and(|timing_error| timing_error.unsigned_abs() > tolerance)
+        })
+    {
+        result.verdict = Verdict::Fail;
+    }
+    println!("\t{}: {}", result.verdict, result.statistics());
+}
+
+/// Waits for the receiving gateway of the link to hear a packet that
+/// `matches`, and returns it along with its foff and the unix time in ms it
+/// arrived at
+async fn hear<F: Fn(&[u8]) -> bool>(
+    receiver: &mut mpsc::Receiver<Message>,
+    link: &Link<'_>,
+    until: Instant,
+    matches: F,
+) -> Option<(RxPk, Option<i64>, u64)> {
+    loop {
+        let remaining = until.checked_duration_since(Instant::now())?;
+        let (rxpk, foff, mac, role) = match timeout(remaining, receiver.recv()).await {
+            Ok(message) => message.expect("Channels should never close"),
+            Err(_) => return None,
+        };
+        if mac != link.rx_mac || role != link.receiver_role {
+            continue;
+        }
+        if base64::decode(rxpk.get_data()).is_ok_and(|data| matches(&data)) {
+            return Some((rxpk, foff, report::now_ms()));
+        }
+    }
+}
+
+pub(crate) fn channel_result(
+    tx_mac: &MacAddress,
+    rx_mac: &MacAddress,
+    index: usize,
+    frequency: usize,
+    datr: &str,
+    power: u64,
+) -> ChannelResult {
+    ChannelResult {
+        index,
+        frequency,
+        datr: datr.to_string(),
+        power,
+        ipol: false,
+        crc: true,
+        verdict: Verdict::Timeout,
+        tx_mac: report::mac_to_string(tx_mac),
+        rx_mac: report::mac_to_string(rx_mac),
+        sent: 0,
+        received: 0,
+        mismatched: 0,
+        stale: 0,
+        duplicates: 0,
+        corrupted: 0,
+        skipped: 0,
+        wrong_crc: 0,
+        per: 1.0,
+        rssi: None,
+        snr: None,
+        rssi_margin: None,
+        snr_margin: None,
+        frequency_error: None,
+        ppm: None,
+        timing: None,
+        packets: Vec::new(),
+    }
+}
+
+pub(crate) fn packet_result(sequence: u32) -> PacketResult {
+    PacketResult {
+        sequence,
+        sent_at: None,
+        received_at: None,
+        rssi: None,
+        snr: None,
+        frequency_offset: None,
+        foff: None,
+        crc: None,
+        tx_error: None,
+        timing_error: None,
+    }
+}
+
+/// Reports a packet the gateway did not transmit and tells whether to give
+/// up on the rest of the channel
+fn give_up(link: &Link<'_>, channel: usize, error: TxError, cli_options: &Opt) -> bool {
+    println!(
+        "\tGateway {} did not transmit on {} MHz: {} ({})",
+        report::mac_to_string(&link.tx_mac),
+        channel as f64 / 1_000_000.0,
+        error,
+        error.description()
+    );
+    match cli_options.on_tx_error {
+        TxPolicy::Retry => !error.is_transient(),
+        TxPolicy::Skip => true,
+        TxPolicy::Continue => false,
+    }
+}
+
+/// Sends the packet through the link, again on transient errors when the
+/// policy says so
+async fn dispatch(
+    link: &mut Link<'_>,
+    downlink: Downlink,
+    purpose: (&str, record::Step),
+    cli_options: &Opt,
+) -> Result<(), TxError> {
+    let mut attempt = 0;
+    loop {
+        let token = rand::random();
+        let frame = wire::pull_resp(token, &downlink);
+        if let Some(recorder) = &link.server.recorder {
+            let macs = (&link.tx_mac, &link.rx_mac);
+            recorder.down(link.server.port, macs, purpose, &frame);
+        }
+        let result = link
+            .server
+            .downlinks
+            .send(&link.tx_mac, token, &frame, Duration::from_secs(5))
+            .await
+            .map_err(|error| TxError::from(&error));
+        // the gateway counts whatever reached it in its stat
+        if !matches!(result, Err(TxError::UnknownMac) | Err(TxError::Dispatch)) {
+            link.monitor.record_downlink(&link.tx_mac, result.is_ok());
+        }
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        if cli_options.on_tx_error != TxPolicy::Retry
+            || !error.is_transient()
+            || attempt == cli_options.tx_retries
+        {
+            return Err(error);
+        }
+        attempt += 1;
+        println!(
+            "\tTransmit failed with {}, retrying ({}/{})",
+            error, attempt, cli_options.tx_retries
+        );
+        delay_for(Duration::from_millis(100)).await;
+    }
+}
+
+/// Matches incoming packets to the ones sent on the channel until the deadline.
+/// When `until_done` is set, stops as soon as every dispatched packet is in
+async fn receive(
+    receiver: &mut mpsc::Receiver<Message>,
+    link: &Link<'_>,
+    nonce: u32,
+    result: &mut ChannelResult,
+    until: Instant,
+    until_done: bool,
+) {
+    loop {
+        if until_done
+            && result
+                .packets
+                .iter()
+                .all(|packet| packet.sent_at.is_none() || packet.received_at.is_some())
+        {
+            return;
+        }
+        let remaining = match until.checked_duration_since(Instant::now()) {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        let (rxpk, foff, mac, role) = match timeout(remaining, receiver.recv()).await {
+            Ok(message) => message.expect("Channels should never close"),
+            Err(_) => return,
+        };
+
+        if mac != link.rx_mac || role != link.receiver_role {
+            continue;
+        }
+        record_packet(result, nonce, &rxpk, foff, report::now_ms());
+    }
+}
+
+/// Matches a packet heard by the receiver of `result` to the one sent, at
+/// `received_at` in unix ms
+pub(crate) fn record_packet(
+    result: &mut ChannelResult,
+    nonce: u32,
+    rxpk: &RxPk,
+    foff: Option<i64>,
+    received_at: u64,
+) {
+    let data = match base64::decode(rxpk.get_data()) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+    let payload = match Payload::decode(&data) {
+        Ok(payload) if payload.nonce == nonce => payload,
+        Err(payload::Error::Checksum(payload)) if payload.nonce == nonce => {
+            println!(
+                "\tReceived corrupted packet: {}",
+                payload::Error::Checksum(payload)
+            );
+            result.corrupted += 1;
+            return;
+        }
+        // someone else's traffic
+        _ => return,
+    };
+    let packet = match result
+        .packets
+        .iter_mut()
+        .find(|packet| packet.sequence == payload.sequence)
+    {
+        Some(packet) if payload.channel as usize == result.index => packet,
+        _ => {
+            println!(
+                "\tReceived stale packet #{} from channel {}",
+                payload.sequence,
+                payload.channel as usize + 1
+            );
+            result.stale += 1;
+            return;
+        }
+    };
+    if packet.received_at.is_some() {
+        println!("\tReceived duplicate of packet #{}", packet.sequence);
+        result.duplicates += 1;
+        return;
+    }
+    let freq = result.frequency as f64 / 1_000_000.0;
+    let data_rate = DataRate::from_str(&rxpk.get_datarate()).ok();
+    if data_rate.is_some()
+        && data_rate == DataRate::from_str(&result.datr).ok()
+        && (rxpk.get_frequency() - freq).abs() < 0.1
+    {
+        // FSK packets come without an SNR
+        let snr = match data_rate {
+            Some(DataRate::Lora { .. }) => Some(rxpk.get_snr()),
+            _ => None,
+        };
+        println!(
+            "\tReceived expected packet #{}! RSSI = {}, SNR = {}",
+            packet.sequence,
+            rxpk.get_rssi(),
+            report::or_empty(snr)
+        );
+        packet.received_at = Some(received_at);
+        packet.rssi = Some(rxpk.get_rssi());
+        packet.snr = snr;
+        packet.frequency_offset =
+            Some(((rxpk.get_frequency() - freq) * 1_000_000.0).round() as i64);
+        packet.foff = foff;
+        packet.crc = Some(crc_status(rxpk));
+    } else {
+        println!(
+            "\tReceived packet #{} on wrong channel or data rate: {} MHz, {}",
+            packet.sequence,
+            rxpk.get_frequency(),
+            rxpk.get_datarate()
+        );
+        result.mismatched += 1;
+    }
+}
+
+fn crc_status(rxpk: &RxPk) -> CrcStatus {
+    match rxpk {
+        RxPk::V1(rxpk) => CrcStatus::from_stat(rxpk.stat),
+        RxPk::V2(rxpk) => CrcStatus::from_stat(rxpk.stat),
+    }
+}
+
+fn create_packet(
+    channel: &usize,
+    datr: &str,
+    power: u64,
+    data: Vec<u8>,
+    at: At,
+    fdev: u64,
+) -> pull_resp::TxPk {
+    let size = data.len() as u64;
+    let data = base64::encode(data);
+    let (imme, tmst, tmms) = match at {
+        At::Now => (true, 0, None),
+        At::Counter(tmst) => (false, tmst, None),
+        // the tmst is left out on the wire, see Downlink
+        At::Gps(tmms) => (false, 0, Some(StringOrNum::N(tmms))),
+    };
+    let freq = *channel as f64 / 1_000_000.0;
+    // FSK takes its bitrate as datr, which goes out as a number, see wire
+    let (modu, datr, fdev, prea) = match DataRate::from_str(datr) {
+        Ok(DataRate::Fsk { bitrate }) => ("FSK", bitrate.to_string(), Some(fdev), Some(5)),
+        _ => ("LORA", datr.to_string(), None, None),
+    };
+
+    pull_resp::TxPk {
+        imme,
+        tmst: StringOrNum::N(tmst.into()),
+        freq,
+        rfch: 0,
+        powe: power,
+        modu: modu.into(),
+        datr,
+        codr: "4/5".into(),
+        ipol: false,
+        size,
+        data,
+        tmms,
+        fdev,
+        prea,
+        ncrc: None,
+    }
+}
+
+/// When a gateway is to transmit a packet
+#[derive(Debug, Clone, Copy)]
+enum At {
+    /// as soon as it gets it
+    Now,
+    /// at this value of its concentrator counter, in µs
+    Counter(u32),
+    /// at this GPS time, in ms
+    Gps(u64),
+}
+
+fn time_on_air(datr: &str, size: usize) -> Duration {
+    DataRate::from_str(datr)
+        .map(|data_rate| data_rate.time_on_air(size))
+        .unwrap_or_default()
+}
@@ -0,0 +1,122 @@
+use semtech_udp::{server_runtime, tx_ack};
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+/// Why a downlink was not transmitted: the error code of the packet
+/// forwarder's TX_ACK, or what went wrong before one came back
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TxError {
+    TooLate,
+    TooEarly,
+    CollisionPacket,
+    CollisionBeacon,
+    TxFreq,
+    TxPower,
+    GpsUnlocked,
+    SendLbt,
+    SendFail,
+    /// no TX_ACK came back in time
+    AckTimeout,
+    /// the gateway is not connected to the server
+    UnknownMac,
+    /// the server could not send the PULL_RESP
+    Dispatch,
+}
+
+impl TxError {
+    /// Whether sending the same packet again could succeed. The others are
+    /// refusals which will not change until the gateway is reconfigured
+    pub fn is_transient(&self) -> bool {
+        !matches!(
+            self,
+            TxError::TxFreq | TxError::TxPower | TxError::GpsUnlocked | TxError::UnknownMac
+        )
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TxError::TooLate => "too late to transmit at the requested time",
+            TxError::TooEarly => "requested transmit time too far in the future",
+            TxError::CollisionPacket => "another packet is already scheduled then",
+            TxError::CollisionBeacon => "a beacon is already scheduled then",
+            TxError::TxFreq => "transmit frequency is rejected",
+            TxError::TxPower => "transmit power is rejected",
+            TxError::GpsUnlocked => "GPS is not locked",
+            TxError::SendLbt => "channel was busy (listen before talk)",
+            TxError::SendFail => "concentrator failed to transmit",
+            TxError::AckTimeout => "no TX_ACK received",
+            TxError::UnknownMac => "gateway is not connected",
+            TxError::Dispatch => "downlink could not be sent to the gateway",
+        }
+    }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TxError::TooLate => "TOO_LATE",
+            TxError::TooEarly => "TOO_EARLY",
+            TxError::CollisionPacket => "COLLISION_PACKET",
+            TxError::CollisionBeacon => "COLLISION_BEACON",
+            TxError::TxFreq => "TX_FREQ",
+            TxError::TxPower => "TX_POWER",
+            TxError::GpsUnlocked => "GPS_UNLOCKED",
+            TxError::SendLbt => "SEND_LBT",
+            TxError::SendFail => "SEND_FAIL",
+            TxError::AckTimeout => "ACK_TIMEOUT",
+            TxError::UnknownMac => "UNKNOWN_MAC",
+            TxError::Dispatch => "DISPATCH",
+        };
+        f.pad(name)
+    }
+}
+
+impl From<&server_runtime::Error> for TxError {
+    fn from(error: &server_runtime::Error) -> TxError {
+        match error {
+            server_runtime::Error::AckError(error) => match error {
+                tx_ack::Error::TOO_LATE => TxError::TooLate,
+                tx_ack::Error::TOO_EARLY => TxError::TooEarly,
+                tx_ack::Error::COLLISION_PACKET => TxError::CollisionPacket,
+                tx_ack::Error::COLLISION_BEACON => TxError::CollisionBeacon,
+                tx_ack::Error::TX_FREQ => TxError::TxFreq,
+                tx_ack::Error::TX_POWER => TxError::TxPower,
+                tx_ack::Error::GPS_UNLOCKED => TxError::GpsUnlocked,
+                tx_ack::Error::SEND_LBT => TxError::SendLbt,
+                // NONE is not an error, but it was reported as one
+                tx_ack::Error::SEND_FAIL | tx_ack::Error::NONE => TxError::SendFail,
+            },
+            server_runtime::Error::SendTimeout | server_runtime::Error::AckRecvError => {
+                TxError::AckTimeout
+            }
+            server_runtime::Error::UnknownMac => TxError::UnknownMac,
+            _ => TxError::Dispatch,
+        }
+    }
+}
+
+/// What to do when the gateway does not transmit a packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxPolicy {
+    /// send the packet again on transient errors, skip the rest of the
+    /// channel on refusals
+    Retry,
+    /// skip the rest of the channel on any error
+    Skip,
+    /// carry on with the next packet of the channel
+    Continue,
+}
+
+impl FromStr for TxPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TxPolicy, String> {
+        match s {
+            "retry" => Ok(TxPolicy::Retry),
+            "skip" => Ok(TxPolicy::Skip),
+            "continue" => Ok(TxPolicy::Continue),
+            _ => Err(format!("unknown TX error policy: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! The Semtech UDP server the gateways connect to.
+//!
+//! It raises the same events as the runtime of semtech-udp 0.4.1, but sends
+//! PULL_RESP the way [`wire`](crate::wire) writes them, acknowledges the
+//! PUSH_DATA semtech-udp cannot parse, and fails a downlink to a gateway
+//! which is not connected right away.
+use semtech_udp::{
+    parser::Parser,
+    server_runtime::{Error, Event},
+    tx_ack, MacAddress, Packet, Up,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::{
+    net::{
+        udp::{RecvHalf, SendHalf},
+        UdpSocket,
+    },
+    sync::{self, oneshot},
+    time::{self, Duration},
+};
+
+const PUSH_DATA: u8 = 0;
+const PUSH_ACK: u8 = 1;
+const PULL_DATA: u8 = 2;
+const PULL_ACK: u8 = 4;
+
+/// Gateways and the address they last sent a PULL_DATA from
+pub(crate) type Clients = Vec<(MacAddress, SocketAddr)>;
+
+// PULL_RESP waiting for their TX_ACK, by token
+type Pending = Vec<(u16, oneshot::Sender<tx_ack::Packet>)>;
+
+/// The events semtech-udp raises for a `frame` from `src`, in the same
+/// order. A PULL_DATA updates the `clients`
+pub(crate) fn events(frame: &[u8], src: SocketAddr, clients: &mut Clients) -> Vec<Event> {
+    let up = match Packet::parse(frame) {
+        Ok(Packet::Up(up)) => up,
+        _ => return vec![Event::UnableToParseUdpFrame(frame.to_vec())],
+    };
+    let mut events = vec![Event::RawPacket(up.clone())];
+    match up {
+        Up::PullData(pull_data) => {
+            let mac = pull_data.gateway_mac;
+            match clients.iter_mut().find(|(client, _)| *client == mac) {
+                Some((_, addr)) if *addr == src => (),
+                Some((_, addr)) => {
+                    *addr = src;
+                    events.push(Event::UpdateClient((mac, src)));
+                }
+                None => {
+                    clients.push((mac, src));
+                    events.push(Event::NewClient((mac, src)));
+                }
+            }
+        }
+        Up::PushData(push_data) => {
+            for rxpk in push_data.data.rxpk.unwrap_or_default() {
+                events.push(Event::PacketReceived(rxpk, push_data.gateway_mac));
+            }
+        }
+        Up::TxAck(_) => (),
+    }
+    events
+}
+
+/// Binds a server to `port`, or to a port the OS picks for 0
+pub async fn bind(port: u16) -> std::io::Result<(Uplinks, Arc<Downlinks>)> {
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).await?;
+    let port = socket.local_addr()?.port();
+    let (socket_rx, socket_tx) = socket.split();
+    let downlinks = Arc::new(Downlinks {
+        socket: sync::Mutex::new(socket_tx),
+        clients: Mutex::new(Vec::new()),
+        pending: Mutex::new(Vec::new()),
+    });
+    let uplinks = Uplinks {
+        port,
+        socket: socket_rx,
+        downlinks: downlinks.clone(),
+        buf: vec![0u8; 65_536],
+    };
+    Ok((uplinks, downlinks))
+}
+
+/// The receiving side of a server
+pub struct Uplinks {
+    port: u16,
+    socket: RecvHalf,
+    downlinks: Arc<Downlinks>,
+    buf: Vec<u8>,
+}
+
+impl Uplinks {
+    /// The port the server is bound to
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Waits for the next datagram from a gateway, acknowledges it and
+    /// returns it along with the events it raises
+    pub async fn recv(&mut self) -> (Vec<u8>, Vec<Event>) {
+        loop {
+            let (n, src) = match self.socket.recv_from(&mut self.buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    println!("UDP receive error: {}", e);
+                    continue;
+                }
+            };
+            let frame = &self.buf[..n];
+            self.downlinks.acknowledge(frame, src).await;
+            let events = {
+                let mut clients = self
+                    .downlinks
+                    .clients
+                    .lock()
+                    .expect("no one panics holding the lock");
+                events(frame, src, &mut clients)
+            };
+            for event in &events {
+                if let Event::RawPacket(Up::TxAck(tx_ack)) = event {
+                    self.downlinks.resolve(tx_ack);
+                }
+            }
+            return (frame.to_vec(), events);
+        }
+    }
+}
+
+/// The sending side of a server
+pub struct Downlinks {
+    socket: sync::Mutex<SendHalf>,
+    clients: Mutex<Clients>,
+    pending: Mutex<Pending>,
+}
+
+impl Downlinks {
+    /// Sends the PULL_RESP `frame` with `token` to the gateway `mac` and
+    /// waits for its TX_ACK
+    pub async fn send(
+        &self,
+        mac: &MacAddress,
+        token: u16,
+        frame: &[u8],
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        let addr = self
+            .clients
+            .lock()
+            .expect("no one panics holding the lock")
+            .iter()
+            .find(|(client, _)| client == mac)
+            .map(|(_, addr)| *addr)
+            .ok_or(Error::UnknownMac)?;
+        let (sender, receiver) = oneshot::channel();
+        self.pending().push((token, sender));
+        if let Err(e) = self.socket.lock().await.send_to(frame, &addr).await {
+            self.forget(token);
+            return Err(Error::UdpError(e));
+        }
+        let tx_ack = match time::timeout(timeout, receiver).await {
+            Ok(Ok(tx_ack)) => tx_ack,
+            Ok(Err(_)) => return Err(Error::AckRecvError),
+            Err(_) => {
+                self.forget(token);
+                return Err(Error::SendTimeout);
+            }
+        };
+        match tx_ack.get_error() {
+            Some(error) => Err(Error::AckError(error)),
+            None => Ok(()),
+        }
+    }
+
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().expect("no one panics holding the lock")
+    }
+
+    fn forget(&self, token: u16) {
+        self.pending().retain(|(pending, _)| *pending != token);
+    }
+
+    fn resolve(&self, tx_ack: &tx_ack::Packet) {
+        let mut pending = self.pending();
+        match pending
+            .iter()
+            .position(|(token, _)| *token == tx_ack.random_token)
+        {
+            Some(index) => {
+                let (_, sender) = pending.remove(index);
+                let _ = sender.send(tx_ack.clone());
+            }
+            None => println!("TX_ACK received for unknown token {}", tx_ack.random_token),
+        }
+    }
+
+    // answers a PUSH_DATA or a PULL_DATA, whether semtech-udp can parse it
+    // or not
+    async fn acknowledge(&self, frame: &[u8], src: SocketAddr) {
+        let ack = match frame.get(3) {
+            Some(&PUSH_DATA) => PUSH_ACK,
+            Some(&PULL_DATA) => PULL_ACK,
+            _ => return,
+        };
+        let ack = [frame[0], frame[1], frame[2], ack];
+        // the gateway asks again if this gets lost
+        let _ = self.socket.lock().await.send_to(&ack, &src).await;
+    }
+}
@@ -0,0 +1,190 @@
+//! Packets as they appear on the wire.
+//!
+//! Packet forwarders report the `datr` of FSK packets as a number of bits
+//! per second and leave out `codr` and `lsnr`, and report a failed CRC as a
+//! `stat` of -1. semtech-udp 0.4.1 accepts none of these: it hands such
+//! PUSH_DATA over as unparsable frames, along with the `stat` they carry.
+//! Its txpk always carries a `tmst`, which the reference packet forwarder
+//! schedules by ahead of a `tmms`, has no `nhdr`, and takes the `datr` of
+//! FSK as a string, where packet forwarders read a number. Its v1 rxpk
+//! drops the `foff` sx1302_hal's packet forwarder adds to them. These
+//! helpers bridge the two shapes.
+use super::health::GatewayStat;
+use semtech_udp::{pull_resp::TxPk, push_data::RxPk, MacAddress};
+use serde_json::{json, Value};
+
+const HEADER: usize = 12;
+const PUSH_DATA: u8 = 0;
+const PULL_RESP: u8 = 3;
+const PULL_RESP_HEADER: usize = 4;
+const PROTOCOL_VERSION: u8 = 2;
+
+/// A packet for a gateway to transmit
+#[derive(Debug, Clone)]
+pub struct Downlink {
+    pub txpk: TxPk,
+    /// whether the `tmst` of the txpk goes along
+    pub tmst: bool,
+    /// sent without a LoRa header, as Class B beacons are
+    pub implicit_header: bool,
+}
+
+impl From<TxPk> for Downlink {
+    /// A packet with a header, which goes at once or when its `tmst` says,
+    /// unless it has a `tmms`
+    fn from(txpk: TxPk) -> Downlink {
+        Downlink {
+            tmst: txpk.tmms.is_none(),
+            txpk,
+            implicit_header: false,
+        }
+    }
+}
+
+/// The PULL_RESP carrying `downlink`, as packet forwarders read it
+pub fn pull_resp(token: u16, downlink: &Downlink) -> Vec<u8> {
+    let mut txpk = serde_json::to_value(&downlink.txpk).expect("a txpk serializes");
+    let fields = txpk.as_object_mut().expect("a txpk is an object");
+    if !downlink.tmst {
+        fields.remove("tmst");
+    }
+    if downlink.implicit_header {
+        fields.insert("nhdr".into(), json!(true));
+    }
+    if fields.get("modu") == Some(&json!("FSK")) {
+        let bitrate = fields
+            .get("datr")
+            .and_then(Value::as_str)
+            .and_then(|datr| datr.parse::<u64>().ok());
+        if let Some(bitrate) = bitrate {
+            fields.insert("datr".into(), json!(bitrate));
+        }
+    }
+    let mut frame = vec![PROTOCOL_VERSION, (token >> 8) as u8, token as u8, PULL_RESP];
+    frame.extend_from_slice(json!({ "txpk": txpk }).to_string().as_bytes());
+    frame
+}
+
+/// The token and the packet of a PULL_RESP, as [`pull_resp`] writes them
+pub fn parse_pull_resp(frame: &[u8]) -> Option<(u16, Downlink)> {
+    if frame.len() <= PULL_RESP_HEADER || frame[3] != PULL_RESP {
+        return None;
+    }
+    let token = (frame[1] as u16) << 8 | frame[2] as u16;
+    let mut data: Value = serde_json::from_slice(&frame[PULL_RESP_HEADER..]).ok()?;
+    let fields = data.get_mut("txpk")?.as_object_mut()?;
+    // semtech-udp's txpk needs one
+    let tmst = fields.contains_key("tmst");
+    if !tmst {
+        fields.insert("tmst".into(), json!(0));
+    }
+    let implicit_header = fields.remove("nhdr") == Some(json!(true));
+    if fields.get("modu") == Some(&json!("FSK")) {
+        // read as a number, which a string is not
+        let bitrate = fields.get("datr").and_then(Value::as_u64).unwrap_or(0);
+        fields.insert("datr".into(), json!(bitrate.to_string()));
+    }
+    let txpk: TxPk = serde_json::from_value(Value::Object(fields.clone())).ok()?;
+    let downlink = Downlink {
+        txpk,
+        tmst,
+        implicit_header,
+    };
+    Some((token, downlink))
+}
+
+/// The gateway, the packets and the `stat` report of a PUSH_DATA that
+/// semtech-udp could not parse. A packet `stat` of -1 does not fit its
+/// unsigned field and comes through as `u64::MAX`, see
+/// [`CrcStatus`](crate::report::CrcStatus)
+pub fn parse_push_data(frame: &[u8]) -> Option<(MacAddress, Vec<RxPk>, Option<GatewayStat>)> {
+    if frame.len() <= HEADER || frame[3] != PUSH_DATA {
+        return None;
+    }
+    let mut mac = [0u8; 8];
+    mac.copy_from_slice(&frame[4..HEADER]);
+    let mut data: Value = serde_json::from_slice(&frame[HEADER..]).ok()?;
+    let stat = data
+        .get("stat")
+        .and_then(|stat| serde_json::from_value(stat.clone()).ok());
+    let packets = match data.get_mut("rxpk") {
+        Some(rxpk) => rxpk.as_array_mut()?,
+        None => return Some((MacAddress::new(&mac), Vec::new(), stat)),
+    };
+    let packets = packets
+        .iter_mut()
+        .filter_map(|rxpk| {
+            let rxpk = rxpk.as_object_mut()?;
+            if let Some(Value::Number(datr)) = rxpk.get("datr") {
+                let datr = datr.to_string();
+                rxpk.insert("datr".into(), json!(datr));
+            }
+            if rxpk.get("stat").and_then(Value::as_i64) == Some(-1) {
+                rxpk.insert("stat".into(), json!(u64::MAX));
+            }
+            rxpk.entry("codr").or_insert_with(|| json!(""));
+            rxpk.entry("lsnr").or_insert_with(|| json!(0.0));
+            serde_json::from_value(Value::Object(rxpk.clone())).ok()
+        })
+        .collect();
+    Some((MacAddress::new(&mac), packets, stat))
+}
+
+/// The frequency offset (foff) the demodulator measured for `rxpk`, as the
+/// PUSH_DATA `frame` it came in carries it: at the top level of a v1 rxpk,
+/// or in the first `rsig` of a v2 one
+pub fn foff(frame: &[u8], rxpk: &RxPk) -> Option<i64> {
+    if frame.len() <= HEADER || frame[3] != PUSH_DATA {
+        return None;
+    }
+    let data: Value = serde_json::from_slice(&frame[HEADER..]).ok()?;
+    let raw = data.get("rxpk")?.as_array()?.iter().find(|raw| {
+        raw.get("data").and_then(Value::as_str) == Some(&rxpk.get_data())
+            && raw.get("tmst").and_then(Value::as_u64) == Some(*rxpk.get_timestamp())
+    })?;
+    raw.get("foff")
+        .or_else(|| raw.get("rsig")?.get(0)?.get("foff"))
+        .and_then(Value::as_i64)
+}
+
+/// Rewrites the packets of a serialized PUSH_DATA the way a packet
+/// forwarder sends them: FSK ones with a numeric `datr` and without `codr`
+/// or `lsnr`, LoRa ones with `foff` when given. Other frames are left
+/// untouched
+pub fn as_forwarded(frame: &[u8], foff: Option<i64>) -> Vec<u8> {
+    let mut data: Value = match frame.get(3) {
+        Some(&PUSH_DATA) => match serde_json::from_slice(&frame[HEADER..]) {
+            Ok(data) => data,
+            Err(_) => return frame.to_vec(),
+        },
+        _ => return frame.to_vec(),
+    };
+    let mut rewritten = false;
+    if let Some(rxpk) = data.get_mut("rxpk").and_then(Value::as_array_mut) {
+        for rxpk in rxpk.iter_mut().filter_map(Value::as_object_mut) {
+            if rxpk.get("modu") != Some(&json!("FSK")) {
+                if let Some(foff) = foff {
+                    rxpk.insert("foff".into(), json!(foff));
+                    rewritten = true;
+                }
+                continue;
+            }
+            let bitrate = rxpk
+                .get("datr")
+                .and_then(Value::as_str)
+                .and_then(|datr| datr.parse::<u64>().ok());
+            if let Some(bitrate) = bitrate {
+                rxpk.insert("datr".into(), json!(bitrate));
+            }
+            rxpk.remove("codr");
+            rxpk.remove("lsnr");
+            rewritten = true;
+        }
+    }
+    if !rewritten {
+        return frame.to_vec();
+    }
+    let mut forwarded = frame[..HEADER].to_vec();
+    forwarded.extend_from_slice(data.to_string().as_bytes());
+    forwarded
+}
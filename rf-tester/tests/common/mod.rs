@@ -0,0 +1,220 @@
+use rf_tester::{
+    report::Report,
+    simulator::{Gateway, Medium},
+    wire, Opt,
+};
+use semtech_udp::{
+    pull_data,
+    pull_resp::TxPk,
+    push_data::{self, RxPk, RxPkV1},
+    tx_ack, MacAddress, Packet, SerializablePacket,
+};
+use std::{net::SocketAddr, path::PathBuf};
+use structopt::StructOpt;
+use tokio::{
+    net::{
+        udp::{RecvHalf, SendHalf},
+        UdpSocket,
+    },
+    sync::mpsc,
+    time::{self, Duration},
+};
+
+pub const TESTED: u64 = 0x0000_0000_0000_0001;
+pub const CONTROL: u64 = 0x0000_0000_0000_0002;
+
+/// Ports nothing listens on: the OS picks them when binding to port 0
+pub fn free_ports<const N: usize>() -> [u16; N] {
+    // held until all are bound, so that the ports differ
+    let sockets: Vec<std::net::UdpSocket> = (0..N)
+        .map(|_| std::net::UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).unwrap())
+        .collect();
+    let mut ports = [0; N];
+    for (port, socket) in ports.iter_mut().zip(&sockets) {
+        *port = socket.local_addr().unwrap().port();
+    }
+    ports
+}
+
+/// Options for a fast run at SF7 against servers on free ports, on EU868
+/// unless `extra` gives a region
+pub fn opt(extra: &[&str]) -> Opt {
+    let [test_port, control_port] = free_ports();
+    opt_on(test_port, control_port, extra)
+}
+
+/// Like [`opt`], against servers on the given ports
+pub fn opt_on(test_port: u16, control_port: u16, extra: &[&str]) -> Opt {
+    let test_port = test_port.to_string();
+    let control_port = control_port.to_string();
+    let mut args = vec![
+        "rf-tester",
+        "--test-port",
+        &test_port,
+        "--control-port",
+        &control_port,
+        "--datr",
+        "SF7BW125",
+        "--interval",
+        "50",
+        "--timeout",
+        "300",
+    ];
+    if !extra.contains(&"--region") {
+        args.extend_from_slice(&["--region", "EU868"]);
+    }
+    args.extend_from_slice(extra);
+    Opt::from_iter(args)
+}
+
+/// A file of the temporary directory for `name`, apart from other runs of
+/// the suite
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rf-tester-{}-{}.jsonl", name, std::process::id()))
+}
+
+/// Runs rf-tester next to `gateways`, which are started once its servers
+/// are listening
+pub async fn run<F: std::future::Future<Output = ()>>(opt: &Opt, gateways: F) -> Report {
+    run_for(opt, gateways, Duration::from_secs(60)).await
+}
+
+/// Like [`run`], for runs which may take up to `limit`
+pub async fn run_for<F: std::future::Future<Output = ()>>(
+    opt: &Opt,
+    gateways: F,
+    limit: Duration,
+) -> Report {
+    let (report, _) = futures::join!(time::timeout(limit, rf_tester::run(opt)), async {
+        // let the servers bind first
+        time::delay_for(Duration::from_millis(200)).await;
+        gateways.await
+    });
+    report
+        .expect("rf-tester should not hang")
+        .expect("rf-tester should run")
+}
+
+pub fn localhost(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+pub fn mac(mac: u64) -> MacAddress {
+    MacAddress::new(&mac.to_be_bytes())
+}
+
+/// The tested and control gateways, connected to the servers of `opt`
+pub fn gateways(opt: &Opt) -> Vec<Gateway> {
+    vec![
+        Gateway::new(TESTED, localhost(opt.test_ports[0])),
+        Gateway::new(CONTROL, localhost(opt.control_ports[0])),
+    ]
+}
+
+pub async fn spawn(medium: Medium, gateways: Vec<Gateway>) {
+    medium.spawn(gateways).await.expect("gateways should start");
+}
+
+/// A socket speaking to `port` as the gateway `mac`
+pub async fn connect(port: u16, mac: u64) -> UdpSocket {
+    let mut socket = UdpSocket::bind(localhost(0)).await.unwrap();
+    socket.connect(localhost(port)).await.unwrap();
+    send(
+        &mut socket,
+        pull_data::Packet {
+            random_token: 0,
+            gateway_mac: self::mac(mac),
+        }
+        .into(),
+    )
+    .await;
+    socket
+}
+
+pub async fn send(socket: &mut UdpSocket, packet: Packet) {
+    let mut buf = vec![0u8; 1024];
+    let n = packet.serialize(&mut buf).unwrap() as usize;
+    socket.send(&buf[..n]).await.unwrap();
+}
+
+/// Decides whether a hand-rolled gateway refuses a downlink, and with which
+/// TX_ACK error
+pub type Refuse = fn(u64, &TxPk) -> Option<tx_ack::Error>;
+
+/// A pair of hand-rolled gateways which echo every downlink of one to the
+/// server of the other, after passing it through `rewrite`
+pub async fn echo(opt: &Opt, rewrite: fn(&mut RxPkV1), refuse: Refuse) {
+    let (tested_rx, tested_tx) = connect(opt.test_ports[0], TESTED).await.split();
+    let (control_rx, control_tx) = connect(opt.control_ports[0], CONTROL).await.split();
+    let tested = writer(tested_tx, TESTED);
+    let control = writer(control_tx, CONTROL);
+    tokio::spawn(reader(
+        tested_rx,
+        TESTED,
+        tested.clone(),
+        control.clone(),
+        rewrite,
+        refuse,
+    ));
+    tokio::spawn(reader(
+        control_rx, CONTROL, control, tested, rewrite, refuse,
+    ));
+}
+
+fn writer(mut socket: SendHalf, gateway: u64) -> mpsc::Sender<Packet> {
+    let (sender, mut receiver) = mpsc::channel::<Packet>(100);
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 1024];
+        while let Some(mut packet) = receiver.recv().await {
+            if let Packet::Up(up) = &mut packet {
+                up.set_gateway_mac(mac(gateway));
+            }
+            let n = packet.serialize(&mut buf).unwrap() as usize;
+            socket.send(&buf[..n]).await.unwrap();
+        }
+    });
+    sender
+}
+
+async fn reader(
+    mut socket: RecvHalf,
+    gateway: u64,
+    mut acks: mpsc::Sender<Packet>,
+    mut other: mpsc::Sender<Packet>,
+    rewrite: fn(&mut RxPkV1),
+    refuse: Refuse,
+) {
+    let mut buf = vec![0u8; 1024];
+    while let Ok(n) = socket.recv(&mut buf).await {
+        if let Some((token, downlink)) = wire::parse_pull_resp(&buf[..n]) {
+            let txpk = &downlink.txpk;
+            let tx_ack = tx_ack::Packet {
+                random_token: token,
+                gateway_mac: mac(gateway),
+                data: refuse(gateway, txpk).map(tx_ack::TxPkNack::new),
+            };
+            let refused = tx_ack.has_error();
+            acks.send(tx_ack.into()).await.unwrap();
+            if refused {
+                continue;
+            }
+            let mut rxpk = RxPkV1 {
+                chan: 0,
+                codr: txpk.codr.clone(),
+                data: txpk.data.clone(),
+                datr: txpk.datr.clone(),
+                freq: txpk.freq,
+                lsnr: 7.0,
+                modu: txpk.modu.clone(),
+                rfch: 0,
+                rssi: -80,
+                size: txpk.size,
+                stat: 1,
+                tmst: 0,
+            };
+            rewrite(&mut rxpk);
+            let push_data = push_data::Packet::from_rxpk(RxPk::V1(rxpk));
+            other.send(push_data.into()).await.unwrap();
+        }
+    }
+}
@@ -0,0 +1,1096 @@
+//! Runs rf-tester against simulated gateways over loopback UDP. Every test
+//! uses its own pair of ports so that they can run concurrently
+mod common;
+
+use common::{
+    connect, echo, free_ports, gateways, localhost, opt, opt_on, run, run_for, spawn, temp_path,
+    CONTROL, TESTED,
+};
+use regions::BEACON_PERIOD;
+use rf_tester::{
+    gps,
+    record::Entry,
+    report::{self, ChannelResult, CrcStatus, GatewayReport, Report},
+    simulator::{Gateway, Medium},
+    tx_error::TxError,
+    Verdict,
+};
+use semtech_udp::tx_ack;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+use structopt::StructOpt;
+use tokio::time::{self, Duration};
+
+/// Results of the only gateway under test
+fn tested(report: &Report) -> &GatewayReport {
+    &report.gateways[0]
+}
+
+fn channels(report: &Report) -> impl Iterator<Item = &ChannelResult> {
+    report
+        .gateways
+        .iter()
+        .flat_map(|gateway| gateway.directions.iter())
+        .flat_map(|direction| direction.channels.iter())
+}
+
+#[tokio::test]
+async fn both_directions_pass() {
+    let opt = opt(&["--count", "2"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let directions = &tested(&report).directions;
+    let names: Vec<&str> = directions.iter().map(|d| d.direction).collect();
+    assert_eq!(names, ["TX", "RX"]);
+    let (tx, rx) = (&directions[0], &directions[1]);
+    assert_eq!(tx.channels.len(), 9);
+    assert_eq!(rx.channels.len(), 9);
+    assert_eq!(tx.channels[0].tx_mac, "0000000000000001");
+    assert_eq!(tx.channels[0].rx_mac, "0000000000000002");
+    assert_eq!(rx.channels[0].tx_mac, "0000000000000002");
+    assert_eq!(rx.channels[0].rx_mac, "0000000000000001");
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Pass);
+        assert_eq!((channel.sent, channel.received), (2, 2));
+        assert_eq!(channel.rssi.as_ref().unwrap().mean, -60.0);
+    }
+}
+
+#[tokio::test]
+async fn control_gateway_connects_first() {
+    let opt = opt(&[]);
+    let mut gateways = gateways(&opt);
+    gateways.reverse();
+    let report = run(&opt, spawn(Medium::default(), gateways)).await;
+
+    assert!(report.passed());
+    assert_eq!(
+        tested(&report).directions[0].channels[0].tx_mac,
+        "0000000000000001"
+    );
+}
+
+#[tokio::test]
+async fn lost_packets_time_out() {
+    let medium = Medium {
+        loss: 1.0,
+        ..Medium::default()
+    };
+    let opt = opt(&[]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Timeout);
+        assert_eq!((channel.sent, channel.received), (1, 0));
+    }
+}
+
+#[tokio::test]
+async fn partial_loss_is_measured() {
+    let medium = Medium {
+        loss: 0.5,
+        seed: 7,
+        ..Medium::default()
+    };
+    let opt = opt(&["--count", "4", "--max-per", "1"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    let (sent, received) = channels(&report).fold((0, 0), |(sent, received), channel| {
+        assert_eq!(
+            channel.per,
+            1.0 - channel.received as f64 / channel.sent as f64
+        );
+        (sent + channel.sent, received + channel.received)
+    });
+    assert_eq!(sent, 72);
+    assert!(received > 0 && received < sent);
+}
+
+#[tokio::test]
+async fn frequency_error_is_reported() {
+    let medium = Medium {
+        rssi: -95,
+        snr: -3.5,
+        frequency_error: 1500,
+        ..Medium::default()
+    };
+    let opt = opt(&[]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(report.passed());
+    for channel in channels(&report) {
+        let packet = &channel.packets[0];
+        // reported at the centre of the channel, off by foff
+        assert_eq!(packet.frequency_offset, Some(0));
+        assert_eq!(packet.foff, Some(1500));
+        assert_eq!(channel.frequency_error.unwrap().mean, 1500.0);
+        let ppm = 1500.0 / channel.frequency as f64 * 1_000_000.0;
+        assert!((channel.ppm.unwrap() - ppm).abs() < 1e-9);
+        assert_eq!(packet.rssi, Some(-95));
+        assert_eq!(packet.snr, Some(-3.5));
+    }
+}
+
+#[tokio::test]
+async fn frequency_error_without_foff_is_unmeasured() {
+    let medium = Medium {
+        frequency_error: 20_000,
+        foff: false,
+        ..Medium::default()
+    };
+    let opt = opt(&["--max-ppm", "15"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.packets[0].foff, None);
+        assert_eq!(channel.frequency_error, None);
+        assert_eq!(channel.ppm, None);
+        assert!(channel.statistics().contains("frequency error unmeasured"));
+        assert_eq!(channel.ppm_cell(), "n/a");
+    }
+}
+
+#[tokio::test]
+async fn drifting_oscillator_fails() {
+    let medium = Medium {
+        frequency_error: 20_000,
+        ..Medium::default()
+    };
+    let opt = opt(&["--max-ppm", "15"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!((channel.received, channel.mismatched), (1, 0));
+        assert!(channel.ppm.unwrap() > 22.0);
+    }
+    assert!(tested(&report).directions[0].summary().contains("+23.0"));
+}
+
+#[tokio::test]
+async fn weak_links_miss_their_margins() {
+    let medium = Medium {
+        rssi: -95,
+        snr: -3.5,
+        ..Medium::default()
+    };
+    let opt = opt(&["--min-rssi", "-100", "--min-snr", "0"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.rssi_margin, Some(5.0));
+        assert_eq!(channel.snr_margin, Some(-3.5));
+        assert!(channel.statistics().contains("SNR margin = -3.5 dB"));
+    }
+    assert!(tested(&report).directions[0]
+        .summary()
+        .contains("+5.0/-3.5"));
+}
+
+#[tokio::test]
+async fn rssi_must_fall_in_its_window() {
+    let opt = opt(&["--rssi-window", "-65", "-58"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.rssi_margin, Some(2.0));
+        assert_eq!(channel.snr_margin, None);
+    }
+
+    let opt = common::opt(&["--rssi-window", "-70", "-65"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.rssi_margin, Some(-5.0));
+    }
+}
+
+#[tokio::test]
+async fn wrong_frequency_is_mismatched() {
+    let medium = Medium {
+        frequency_error: 200_000,
+        ..Medium::default()
+    };
+    let opt = opt(&[]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!((channel.received, channel.mismatched), (0, 1));
+    }
+}
+
+#[tokio::test]
+async fn wrong_data_rate_is_mismatched() {
+    let opt = opt(&[]);
+    let report = run(
+        &opt,
+        echo(&opt, |rxpk| rxpk.datr = "SF8BW125".into(), |_, _| None),
+    )
+    .await;
+
+    assert!(!report.passed());
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!((channel.received, channel.mismatched), (0, 1));
+    }
+}
+
+#[tokio::test]
+async fn echoed_packets_pass() {
+    let opt = opt(&[]);
+    let report = run(&opt, echo(&opt, |_| (), |_, _| None)).await;
+
+    assert!(report.passed());
+}
+
+#[tokio::test]
+async fn unparsable_frames_are_ignored() {
+    let opt = opt(&[]);
+    let report = run(&opt, async {
+        for port in &[opt.test_ports[0], opt.control_ports[0]] {
+            let mut socket = tokio::net::UdpSocket::bind(localhost(0)).await.unwrap();
+            socket.connect(localhost(*port)).await.unwrap();
+            socket.send(b"not a semtech frame").await.unwrap();
+            socket.send(&[2, 0, 0, 9]).await.unwrap();
+        }
+        spawn(Medium::default(), gateways(&opt)).await
+    })
+    .await;
+
+    assert!(report.passed());
+}
+
+#[tokio::test]
+async fn downlinks_follow_gateway_to_new_address() {
+    let opt = opt(&[]);
+    let report = run(&opt, async {
+        // the tested gateway is first seen from another address, as if its
+        // packet forwarder had restarted. The stale socket is kept open so
+        // that downlinks sent to it are silently lost
+        let _stale = connect(opt.test_ports[0], TESTED).await;
+        time::delay_for(Duration::from_millis(100)).await;
+        spawn(Medium::default(), gateways(&opt)).await;
+    })
+    .await;
+
+    assert!(report.passed());
+}
+
+#[tokio::test]
+async fn refused_frequencies_fail_without_retries() {
+    let opt = opt(&["--count", "3"]);
+    let report = run(
+        &opt,
+        echo(
+            &opt,
+            |_| (),
+            |gateway, txpk| {
+                if gateway == TESTED && txpk.freq > 868.4 {
+                    Some(tx_ack::Error::TX_FREQ)
+                } else {
+                    None
+                }
+            },
+        ),
+    )
+    .await;
+
+    assert!(!report.passed());
+    let directions = &tested(&report).directions;
+    let (tx, rx) = (&directions[0], &directions[1]);
+    for channel in &tx.channels {
+        if channel.frequency > 868_400_000 {
+            assert_eq!(channel.verdict, Verdict::Fail);
+            assert_eq!((channel.sent, channel.skipped), (0, 2));
+            assert_eq!(channel.tx_errors(), [TxError::TxFreq]);
+            assert!(channel.statistics().contains("TX_FREQ"));
+        } else {
+            assert_eq!(channel.verdict, Verdict::Pass);
+        }
+    }
+    assert!(tx.summary().contains("TX_FREQ"));
+    assert!(rx
+        .channels
+        .iter()
+        .all(|channel| channel.verdict == Verdict::Pass));
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried() {
+    static COLLISIONS: AtomicUsize = AtomicUsize::new(0);
+    let opt = opt(&[]);
+    let report = run(
+        &opt,
+        echo(
+            &opt,
+            |_| (),
+            |_, _| {
+                // every other downlink collides
+                if COLLISIONS.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                    Some(tx_ack::Error::COLLISION_PACKET)
+                } else {
+                    None
+                }
+            },
+        ),
+    )
+    .await;
+
+    assert!(report.passed());
+    assert_eq!(COLLISIONS.load(Ordering::SeqCst), 36);
+}
+
+#[tokio::test]
+async fn transient_errors_are_kept_without_retries() {
+    let opt = opt(&["--count", "2", "--on-tx-error", "continue"]);
+    let report = run(
+        &opt,
+        echo(&opt, |_| (), |_, _| Some(tx_ack::Error::SEND_LBT)),
+    )
+    .await;
+
+    for channel in channels(&report) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.packets.len(), 2);
+        assert_eq!(channel.skipped, 0);
+        assert_eq!(channel.tx_errors(), [TxError::SendLbt]);
+    }
+}
+
+#[tokio::test]
+async fn scheduled_answers_land_in_rx1() {
+    // three channels, as each answer waits a second for RX1
+    let opt = opt(&["--region", "EU433", "--schedule", "rx1"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let scheduled = &tested(&report).directions[2];
+    assert_eq!(scheduled.direction, "SCHED");
+    assert_eq!(scheduled.channels.len(), 3);
+    for channel in &scheduled.channels {
+        assert_eq!(channel.tx_mac, "0000000000000001");
+        assert!(channel.packets[0].timing_error.is_some());
+    }
+}
+
+#[tokio::test]
+#[ignore = "holds the simulated gateways to the wall clock"]
+async fn scheduled_answers_land_on_time() {
+    let opt = opt(&["--region", "EU433", "--schedule", "rx1"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    for channel in &tested(&report).directions[2].channels {
+        let timing_error = channel.packets[0].timing_error.unwrap();
+        assert!(timing_error.abs() <= 50, "{} ms off", timing_error);
+    }
+}
+
+#[tokio::test]
+async fn late_answers_are_reported() {
+    let opt = opt(&["--schedule", "rx1", "--count", "2"]);
+    let report = run(
+        &opt,
+        echo(
+            &opt,
+            |_| (),
+            |_, txpk| {
+                if txpk.imme {
+                    None
+                } else {
+                    Some(tx_ack::Error::TOO_LATE)
+                }
+            },
+        ),
+    )
+    .await;
+
+    assert!(!report.passed());
+    for channel in &tested(&report).directions[2].channels {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.tx_errors(), [TxError::TooLate]);
+    }
+}
+
+#[tokio::test]
+async fn skipped_uplinks_add_up_with_skipped_answers() {
+    static REFUSE_UPLINK: AtomicBool = AtomicBool::new(false);
+    let opt = opt(&["--schedule", "rx1", "--count", "3"]);
+    let report = run(
+        &opt,
+        echo(
+            &opt,
+            |_| (),
+            |gateway, txpk| {
+                if gateway == TESTED && !txpk.imme {
+                    // gives up on the channel, and the control gateway
+                    // fails the first uplink of the next one
+                    REFUSE_UPLINK.store(true, Ordering::SeqCst);
+                    Some(tx_ack::Error::TX_FREQ)
+                } else if gateway == CONTROL && REFUSE_UPLINK.swap(false, Ordering::SeqCst) {
+                    Some(tx_ack::Error::TX_POWER)
+                } else {
+                    None
+                }
+            },
+        ),
+    )
+    .await;
+
+    let scheduled = &tested(&report).directions[2];
+    assert_eq!(scheduled.direction, "SCHED");
+    for channel in &scheduled.channels {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.skipped, 2);
+    }
+}
+
+#[tokio::test]
+#[ignore = "holds the simulated gateways to the wall clock"]
+async fn gps_scheduled_packets_land_on_time() {
+    let opt = opt(&["--region", "EU433", "--gps"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let gps = &tested(&report).directions[2];
+    assert_eq!(gps.direction, "GPS");
+    for channel in &gps.channels {
+        let timing_error = channel.packets[0].timing_error.unwrap();
+        assert!(timing_error.abs() <= 50, "{} ms off", timing_error);
+    }
+}
+
+#[tokio::test]
+async fn gps_scheduling_needs_a_fix() {
+    let medium = Medium {
+        gps: false,
+        ..Medium::default()
+    };
+    let opt = opt(&["--gps", "--count", "2"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    for channel in &tested(&report).directions[2].channels {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.tx_errors(), [TxError::GpsUnlocked]);
+        assert_eq!(channel.skipped, 1);
+    }
+}
+
+#[tokio::test]
+async fn fsk_channel_is_tested() {
+    let opt = opt(&["--fsk", "--fdev", "20000"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    for direction in &tested(&report).directions {
+        assert_eq!(direction.channels.len(), 10);
+        let fsk = direction.channels.last().unwrap();
+        assert_eq!(fsk.datr, "FSK50000");
+        assert_eq!(fsk.frequency, 868_800_000);
+        assert_eq!(fsk.received, 1);
+        assert_eq!(fsk.rssi.unwrap().mean, -60.0);
+        assert_eq!(fsk.snr, None);
+    }
+}
+
+#[tokio::test]
+async fn inverted_iq_packets_are_not_heard() {
+    let opt = opt(&["--inverted-iq"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let iq = &tested(&report).directions[2];
+    assert_eq!(iq.direction, "IQ");
+    assert_eq!(iq.channels.len(), 4);
+    // a normal IQ control, then the inverted packets, on each link
+    let tx_macs: Vec<&str> = iq.channels.iter().map(|c| c.tx_mac.as_str()).collect();
+    assert_eq!(
+        tx_macs,
+        [
+            "0000000000000001",
+            "0000000000000001",
+            "0000000000000002",
+            "0000000000000002"
+        ]
+    );
+    for pair in iq.channels.chunks(2) {
+        let (control, inverted) = (&pair[0], &pair[1]);
+        assert!(!control.ipol);
+        assert_eq!(control.verdict, Verdict::Pass);
+        assert_eq!((control.sent, control.received), (1, 1));
+        assert!(inverted.ipol);
+        assert_eq!(inverted.verdict, Verdict::Pass);
+        assert_eq!((inverted.sent, inverted.received), (1, 0));
+    }
+    assert!(iq.summary().contains("\n\tINV 1"));
+}
+
+#[tokio::test]
+async fn hearing_inverted_iq_fails() {
+    let opt = opt(&["--inverted-iq"]);
+    let report = run(&opt, echo(&opt, |_| (), |_, _| None)).await;
+
+    assert!(!report.passed());
+    for channel in &tested(&report).directions[2].channels {
+        let expected = if channel.ipol {
+            Verdict::Fail
+        } else {
+            Verdict::Pass
+        };
+        assert_eq!(channel.verdict, expected);
+        assert_eq!(channel.received, 1);
+    }
+}
+
+#[tokio::test]
+async fn deaf_gateway_fails_inverted_iq() {
+    let opt = opt(&["--inverted-iq"]);
+    let medium = Medium {
+        loss: 1.0,
+        ..Medium::default()
+    };
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(!report.passed());
+    let iq = &tested(&report).directions[2];
+    assert_eq!(iq.channels.len(), 4);
+    for channel in &iq.channels {
+        assert_eq!(channel.received, 0);
+        let expected = if channel.ipol {
+            Verdict::Fail
+        } else {
+            Verdict::Timeout
+        };
+        assert_eq!(channel.verdict, expected);
+    }
+}
+
+#[tokio::test]
+async fn packets_without_crc_are_reported() {
+    let opt = opt(&["--no-crc"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let crc = &tested(&report).directions[2];
+    assert_eq!(crc.direction, "CRC");
+    assert_eq!(crc.channels.len(), 1);
+    assert_eq!(crc.channels[0].packets[0].crc, Some(CrcStatus::None));
+    assert_eq!(
+        tested(&report).directions[0].channels[0].packets[0].crc,
+        Some(CrcStatus::Ok)
+    );
+}
+
+#[tokio::test]
+async fn wrong_crc_status_fails() {
+    let opt = opt(&["--no-crc"]);
+    let report = run(&opt, echo(&opt, |rxpk| rxpk.stat = 0, |_, _| None)).await;
+
+    assert!(!report.passed());
+    let directions = &tested(&report).directions;
+    let (tx, crc) = (&directions[0], &directions[2]);
+    assert_eq!(tx.channels[0].verdict, Verdict::Fail);
+    assert_eq!(tx.channels[0].wrong_crc, 1);
+    assert!(tx.channels[0]
+        .statistics()
+        .contains("1 with wrong CRC status"));
+    assert_eq!(crc.channels[0].verdict, Verdict::Pass);
+}
+
+#[tokio::test]
+async fn batch_of_gateways_is_tested_against_each_control() {
+    let [first_test, second_test, first_control, second_control] = free_ports();
+    let (second_test, second_control) = (second_test.to_string(), second_control.to_string());
+    let ports = [
+        "--test-port",
+        &second_test,
+        "--control-port",
+        &second_control,
+    ];
+    let opt = opt_on(first_test, first_control, &ports);
+    let gateways = vec![
+        Gateway::new(TESTED, localhost(opt.test_ports[0])),
+        Gateway::new(3, localhost(opt.test_ports[1])),
+        Gateway::new(CONTROL, localhost(opt.control_ports[0])),
+        Gateway::new(4, localhost(opt.control_ports[1])),
+    ];
+    let report = run(&opt, spawn(Medium::default(), gateways)).await;
+
+    assert!(report.passed());
+    let macs: Vec<&str> = report.gateways.iter().map(|g| g.mac.as_str()).collect();
+    assert_eq!(macs, ["0000000000000001", "0000000000000003"]);
+    for gateway in &report.gateways {
+        let pairs: Vec<(&str, &str, &str)> = gateway
+            .directions
+            .iter()
+            .map(|d| {
+                (
+                    d.direction,
+                    d.channels[0].tx_mac.as_str(),
+                    d.channels[0].rx_mac.as_str(),
+                )
+            })
+            .collect();
+        let mac = gateway.mac.as_str();
+        let (first, second) = ("0000000000000002", "0000000000000004");
+        let expected = [
+            ("TX", mac, first),
+            ("RX", first, mac),
+            ("TX", mac, second),
+            ("RX", second, mac),
+        ];
+        assert_eq!(pairs, expected);
+    }
+}
+
+#[tokio::test]
+async fn stray_gateways_are_ignored() {
+    let macs = [
+        "--test-mac",
+        "0000000000000001",
+        "--control-mac",
+        "00:00:00:00:00:00:00:02",
+    ];
+    let opt = opt(&macs);
+    // the stray gateway connects to the test port first
+    let mut gateways = gateways(&opt);
+    gateways.insert(0, Gateway::new(9, localhost(opt.test_ports[0])));
+    let report = run(&opt, spawn(Medium::default(), gateways)).await;
+
+    assert!(report.passed());
+    assert_eq!(report.gateways.len(), 1);
+    for channel in channels(&report) {
+        assert_ne!(channel.tx_mac, "0000000000000009");
+        assert_ne!(channel.rx_mac, "0000000000000009");
+    }
+}
+
+#[tokio::test]
+async fn roles_share_a_port_by_mac() {
+    let macs = [
+        "--test-mac",
+        "0000000000000001",
+        "--control-mac",
+        "0000000000000002",
+    ];
+    let [port] = free_ports();
+    let opt = opt_on(port, port, &macs);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let directions = &tested(&report).directions;
+    assert_eq!(directions[0].channels[0].tx_mac, "0000000000000001");
+    assert_eq!(directions[0].channels[0].rx_mac, "0000000000000002");
+}
+
+#[tokio::test]
+async fn sharing_a_port_needs_macs() {
+    let [port] = free_ports();
+    let opt = opt_on(port, port, &[]);
+    let error = rf_tester::run(&opt).await.unwrap_err();
+
+    assert!(error.to_string().contains("--test-mac"));
+}
+
+#[tokio::test]
+async fn fsk_needs_an_fsk_channel() {
+    let args = ["rf-tester", "--region", "AS923_1", "--fsk"];
+    let opt = rf_tester::Opt::from_iter(&args);
+    let error = rf_tester::run(&opt).await.unwrap_err();
+
+    assert!(error.to_string().contains("no FSK channel"));
+}
+
+#[tokio::test]
+async fn stat_counters_match_the_downlinks() {
+    let medium = Medium {
+        stat_interval: 1,
+        ..Medium::default()
+    };
+    let opt = opt(&["--check-stats", "--stat-interval", "1"]);
+    let report = run(&opt, spawn(medium, gateways(&opt))).await;
+
+    assert!(report.passed());
+    let macs: Vec<&str> = report
+        .stats
+        .iter()
+        .map(|check| check.mac.as_str())
+        .collect();
+    assert_eq!(macs, ["0000000000000001", "0000000000000002"]);
+    for check in &report.stats {
+        assert!(check.passed);
+        assert!(check.reports > 0);
+        assert_eq!((check.downlinks, check.transmitted), (9, 9));
+        assert_eq!((check.dwnb, check.txnb), (9, 9));
+    }
+}
+
+#[tokio::test]
+async fn gateways_without_stats_fail_the_check() {
+    let opt = opt(&["--check-stats", "--stat-interval", "1"]);
+    let report = run(&opt, echo(&opt, |_| (), |_, _| None)).await;
+
+    assert!(!report.passed());
+    for check in &report.stats {
+        assert!(!check.passed);
+        assert_eq!((check.reports, check.downlinks), (0, 9));
+    }
+    assert!(report.to_junit().contains("type=\"MISMATCH\""));
+}
+
+#[tokio::test]
+async fn recorded_run_replays_to_the_same_results() {
+    let path = temp_path("replayed-run");
+    let record = path.to_str().unwrap();
+    let opt = opt(&["--count", "2", "--record", record]);
+    let live = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    let replayed = rf_tester::replay::replay(&opt, &path).await.unwrap();
+
+    assert!(live.passed());
+    assert!(replayed.passed());
+    let live_channels: Vec<&ChannelResult> = channels(&live).collect();
+    let replayed_channels: Vec<&ChannelResult> = channels(&replayed).collect();
+    assert_eq!(replayed_channels.len(), live_channels.len());
+    for (live, replayed) in live_channels.iter().zip(replayed_channels) {
+        assert_eq!(replayed.frequency, live.frequency);
+        assert_eq!(
+            (replayed.tx_mac.as_str(), replayed.rx_mac.as_str()),
+            (live.tx_mac.as_str(), live.rx_mac.as_str())
+        );
+        assert_eq!((replayed.sent, replayed.received), (2, 2));
+        assert_eq!(replayed.rssi.as_ref().unwrap().mean, -60.0);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn replay_applies_new_thresholds() {
+    let path = temp_path("replay-thresholds");
+    let record = path.to_str().unwrap();
+    let opt = opt(&["--record", record]);
+    let live = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+    let stricter = opt_on(
+        opt.test_ports[0],
+        opt.control_ports[0],
+        &["--min-rssi", "-50"],
+    );
+
+    let replayed = rf_tester::replay::replay(&stricter, &path).await.unwrap();
+
+    assert!(live.passed());
+    assert!(!replayed.passed());
+    for channel in channels(&replayed) {
+        assert_eq!(channel.verdict, Verdict::Fail);
+        assert_eq!(channel.rssi_margin, Some(-10.0));
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn replay_files_packets_under_their_test() {
+    let path = temp_path("replay-filing");
+    let record = path.to_str().unwrap();
+    // three channels, as each GPS packet waits for a whole second
+    let opt = opt(&[
+        "--region",
+        "EU433",
+        "--count",
+        "2",
+        "--schedule",
+        "rx1",
+        "--gps",
+        "--record",
+        record,
+    ]);
+    let live = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    let replayed = rf_tester::replay::replay(&opt, &path).await.unwrap();
+
+    assert!(live.passed());
+    assert!(replayed.passed());
+    let live = &tested(&live).directions;
+    let replayed = &tested(&replayed).directions;
+    let names: Vec<&str> = replayed.iter().map(|d| d.direction).collect();
+    assert_eq!(names, ["TX", "RX", "SCHED", "GPS"]);
+    for (live, replayed) in live.iter().zip(replayed) {
+        assert_eq!(replayed.channels.len(), live.channels.len());
+        for channel in &replayed.channels {
+            assert_eq!((channel.sent, channel.received), (2, 2));
+            assert_eq!(channel.stale, 0);
+            assert_eq!(channel.ppm, Some(0.0));
+        }
+    }
+
+    // the uplinks are kept as the gateways sent them, foff included
+    let entries = rf_tester::record::read(&path).unwrap();
+    assert!(entries.iter().any(|entry| match entry {
+        Entry::Up { .. } => String::from_utf8_lossy(&entry.frame().unwrap()).contains("\"foff\""),
+        Entry::Down { .. } => false,
+    }));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn soak_sums_up_each_period() {
+    let path = temp_path("soak");
+    let _ = std::fs::remove_file(&path);
+    let soak_report = path.to_str().unwrap();
+    let opt = opt(&[
+        "--count",
+        "1",
+        "--soak",
+        "2s",
+        "--soak-period",
+        "1s",
+        "--soak-report",
+        soak_report,
+    ]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    assert!(report.gateways.is_empty());
+    let soak = report.soak.as_ref().unwrap();
+    assert!(soak.periods.len() >= 2);
+    for (number, period) in soak.periods.iter().enumerate() {
+        assert!(period.passed);
+        let directions: Vec<&str> = period.links.iter().map(|link| link.direction).collect();
+        assert_eq!(directions, ["TX", "RX"]);
+        for link in &period.links {
+            assert_eq!(link.per, 0.0);
+            assert_eq!(link.rssi.unwrap().mean, -60.0);
+            let drift = if number == 0 { None } else { Some(0.0) };
+            assert_eq!(link.rssi_drift, drift);
+        }
+        for gateway in &period.gateways {
+            assert_eq!((gateway.gaps, gateway.reconnects), (0, 0));
+        }
+    }
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    assert_eq!(lines, soak.periods.len());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn keepalive_gaps_fail_the_soak() {
+    let opt = opt(&[
+        "--count",
+        "1",
+        "--soak",
+        "2s",
+        "--soak-period",
+        "1s",
+        "--max-keepalive-gap",
+        "1",
+    ]);
+    // the hand-rolled gateways send a single PULL_DATA
+    let report = run(&opt, echo(&opt, |_| (), |_, _| None)).await;
+
+    assert!(!report.passed());
+    let soak = report.soak.as_ref().unwrap();
+    for mac in &["0000000000000001", "0000000000000002"] {
+        let gaps: u64 = soak
+            .periods
+            .iter()
+            .flat_map(|period| period.gateways.iter())
+            .filter(|gateway| gateway.mac == *mac)
+            .map(|gateway| gateway.gaps)
+            .sum();
+        assert_eq!(gaps, 1);
+    }
+    // a silence goes on failing the periods after the one it started in
+    assert!(soak.periods.iter().all(|period| !period.passed));
+    assert!(soak
+        .periods
+        .iter()
+        .flat_map(|period| period.links.iter())
+        .all(|link| link.passed));
+    assert!(report.to_junit().contains("type=\"KEEPALIVE\""));
+}
+
+fn beacon(report: &Report) -> &ChannelResult {
+    let beacon = tested(report)
+        .directions
+        .iter()
+        .find(|direction| direction.direction == "BCN")
+        .unwrap();
+    &beacon.channels[0]
+}
+
+#[tokio::test]
+async fn beacon_is_sent_in_its_slot() {
+    let path = temp_path("beacon");
+    let record = path.to_str().unwrap();
+    let mut opt = opt(&[
+        "--region", "EU433", "--count", "1", "--beacon", "--record", record,
+    ]);
+    // a slot a few seconds away, past the TX and RX tests
+    let period = BEACON_PERIOD as u64 * 1000;
+    opt.beacon_offset = (gps::gps_ms(report::now_ms()) + 5000) % period;
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    assert_eq!(beacon(&report).frequency, 434_665_000);
+    assert_eq!(beacon(&report).datr, "SF9BW125");
+
+    // sent at a GPS time only, without a header or CRC
+    let entries = rf_tester::record::read(&path).unwrap();
+    let txpk = entries
+        .iter()
+        .rev()
+        .filter(|entry| matches!(entry, Entry::Down { .. }))
+        .map(|entry| {
+            let frame = entry.frame().unwrap();
+            let data: serde_json::Value = serde_json::from_slice(&frame[4..]).unwrap();
+            data["txpk"].clone()
+        })
+        .next()
+        .unwrap();
+    assert!(txpk.get("tmst").is_none());
+    assert_eq!(txpk["tmms"].as_u64().unwrap() % period, opt.beacon_offset);
+    assert_eq!(txpk["nhdr"], true);
+    assert_eq!(txpk["ncrc"], true);
+    assert_eq!(txpk["prea"], 10);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+#[ignore = "waits for the next beacon slot, up to 128 s away"]
+async fn beacon_lands_on_time_in_a_real_slot() {
+    let opt = opt(&["--region", "EU433", "--count", "1", "--beacon"]);
+    let limit = Duration::from_secs(200);
+    let report = run_for(&opt, spawn(Medium::default(), gateways(&opt)), limit).await;
+
+    assert!(report.passed());
+    let timing_error = beacon(&report).packets[0].timing_error.unwrap();
+    assert!(timing_error.abs() <= 50, "{} ms off", timing_error);
+}
+
+#[tokio::test]
+async fn sweep_tests_each_data_rate_on_its_channels() {
+    // three channels, as each SF12 packet is on air for over a second
+    let opt = opt(&[
+        "--region", "EU433", "--count", "1", "--sweep", "--size", "14",
+    ]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let tx = &tested(&report).directions[0];
+    let frequencies = |datr: &str| -> Vec<usize> {
+        tx.channels
+            .iter()
+            .filter(|channel| channel.datr == datr)
+            .map(|channel| channel.frequency)
+            .collect()
+    };
+    let multi_sf = regions::Region::EU433.get_uplink_frequencies();
+    for spreading_factor in 7..=12 {
+        assert_eq!(
+            frequencies(&format!("SF{}BW125", spreading_factor)),
+            multi_sf
+        );
+    }
+    // EU433 has no fat or FSK channel to test SF7BW250 and FSK on
+    assert_eq!(tx.channels.len(), 6 * 3);
+}
+
+#[tokio::test]
+async fn replayed_frames_are_stale() {
+    static LAST: Mutex<String> = Mutex::new(String::new());
+    let opt = opt(&[]);
+    let report = run(
+        &opt,
+        echo(
+            &opt,
+            |rxpk| {
+                // every frame but the first is heard as the one before it
+                let mut last = LAST.lock().unwrap();
+                let data = std::mem::replace(&mut *last, rxpk.data.clone());
+                if !data.is_empty() {
+                    rxpk.data = data;
+                }
+            },
+            |_, _| None,
+        ),
+    )
+    .await;
+
+    assert!(!report.passed());
+    let mut channels = channels(&report);
+    let first = channels.next().unwrap();
+    assert_eq!((first.received, first.stale), (1, 0));
+    for channel in channels {
+        assert_eq!(channel.verdict, Verdict::Timeout);
+        assert_eq!((channel.received, channel.stale), (0, 1));
+    }
+}
+
+#[tokio::test]
+async fn power_sweep_follows_the_pa() {
+    let opt = opt(&["--count", "2", "--power-sweep", "12", "18"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let pwr = tested(&report).directions.last().unwrap();
+    assert_eq!(pwr.direction, "PWR");
+    let power_sweep = tested(&report).power_sweep.as_ref().unwrap();
+    let steps: Vec<(u64, Option<f64>)> = power_sweep
+        .steps
+        .iter()
+        .map(|step| (step.power, step.rssi))
+        .collect();
+    assert_eq!(
+        steps,
+        [
+            (12, Some(-60.0)),
+            (14, Some(-58.0)),
+            (16, Some(-56.0)),
+            (18, Some(-54.0))
+        ]
+    );
+    assert_eq!(power_sweep.slope, Some(1.0));
+    assert!(power_sweep.steps.iter().all(|step| !step.flagged));
+}
+
+#[tokio::test]
+async fn power_sweep_ends_on_its_end() {
+    let opt = opt(&["--power-sweep", "12", "17", "--power-step", "2"]);
+    let report = run(&opt, spawn(Medium::default(), gateways(&opt))).await;
+
+    assert!(report.passed());
+    let power_sweep = tested(&report).power_sweep.as_ref().unwrap();
+    let powers: Vec<u64> = power_sweep.steps.iter().map(|step| step.power).collect();
+    assert_eq!(powers, [12, 14, 16, 17]);
+}
+
+#[tokio::test]
+async fn flat_power_sweep_is_flagged() {
+    let opt = opt(&["--power-sweep", "12", "18"]);
+    // the hand-rolled gateways hear everything at -80 dBm
+    let report = run(&opt, echo(&opt, |_| (), |_, _| None)).await;
+
+    assert!(!report.passed());
+    let power_sweep = tested(&report).power_sweep.as_ref().unwrap();
+    assert!(!power_sweep.passed);
+    assert_eq!(power_sweep.slope, Some(0.0));
+    assert!(power_sweep.slope_flagged);
+    let flagged: Vec<(u64, bool)> = power_sweep
+        .steps
+        .iter()
+        .map(|step| (step.power, step.flagged))
+        .collect();
+    assert_eq!(flagged, [(12, true), (14, false), (16, false), (18, true)]);
+}
@@ -0,0 +1,110 @@
+use rf_tester::payload::{Error, Payload, MAX_SIZE, MIN_SIZE};
+
+const PAYLOAD: Payload = Payload {
+    nonce: 0xDEAD_BEEF,
+    sequence: 42,
+    channel: 3,
+};
+
+#[test]
+fn round_trips_from_min_to_max_size() {
+    for size in [MIN_SIZE, 20, MAX_SIZE] {
+        let bytes = PAYLOAD.encode(size);
+        assert_eq!(bytes.len(), size);
+        assert_eq!(Payload::decode(&bytes), Ok(PAYLOAD));
+    }
+}
+
+#[test]
+#[should_panic(expected = "at least 14 bytes")]
+fn cannot_encode_below_min_size() {
+    PAYLOAD.encode(MIN_SIZE - 1);
+}
+
+#[test]
+fn shorter_than_min_size_is_not_ours() {
+    let bytes = PAYLOAD.encode(MIN_SIZE);
+    assert_eq!(
+        Payload::decode(&bytes[..MIN_SIZE - 1]),
+        Err(Error::TooShort)
+    );
+    assert_eq!(Payload::decode(&[]), Err(Error::TooShort));
+}
+
+#[test]
+fn another_nonce_is_told_apart() {
+    let other = Payload {
+        nonce: PAYLOAD.nonce + 1,
+        ..PAYLOAD
+    };
+    let decoded = Payload::decode(&other.encode(20)).unwrap();
+    assert_ne!(decoded.nonce, PAYLOAD.nonce);
+
+    // the nonce of a frame is covered by its checksum
+    let mut bytes = PAYLOAD.encode(20);
+    bytes[0] ^= 0x01;
+    let corrupted = Payload {
+        nonce: PAYLOAD.nonce ^ 0x0100_0000,
+        ..PAYLOAD
+    };
+    assert_eq!(Payload::decode(&bytes), Err(Error::Checksum(corrupted)));
+}
+
+#[test]
+fn a_flipped_byte_anywhere_is_caught() {
+    let bytes = PAYLOAD.encode(MAX_SIZE);
+    for index in 0..bytes.len() {
+        let mut corrupted = bytes.clone();
+        corrupted[index] ^= 0x80;
+        assert!(
+            matches!(Payload::decode(&corrupted), Err(Error::Checksum(_))),
+            "byte {} flipped unnoticed",
+            index
+        );
+    }
+}
+
+#[test]
+fn a_wrong_crc32_is_caught() {
+    let mut bytes = PAYLOAD.encode(20);
+    let crc = bytes.len() - 4;
+    // CRC-32/ISO-HDLC of everything before it
+    let expected = crc32(&bytes[..crc]).to_be_bytes();
+    assert_eq!(bytes[crc..], expected);
+
+    bytes[crc..].copy_from_slice(&(u32::from_be_bytes(expected) + 1).to_be_bytes());
+    assert_eq!(Payload::decode(&bytes), Err(Error::Checksum(PAYLOAD)));
+}
+
+#[test]
+fn checksum_errors_name_the_packet() {
+    let error = Error::Checksum(PAYLOAD);
+    assert_eq!(
+        error.to_string(),
+        "checksum mismatch on packet #42 for channel 4"
+    );
+}
+
+#[test]
+fn crc32_matches_the_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+// a table-driven CRC-32 to check the bitwise one of the payload against
+fn crc32(bytes: &[u8]) -> u32 {
+    let table: Vec<u32> = (0..256u32)
+        .map(|mut crc| {
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+            crc
+        })
+        .collect();
+    !bytes.iter().fold(0xFFFF_FFFF, |crc, byte| {
+        (crc >> 8) ^ table[((crc ^ *byte as u32) & 0xFF) as usize]
+    })
+}
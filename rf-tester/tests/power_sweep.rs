@@ -0,0 +1,79 @@
+use rf_tester::power_sweep::{fit, PowerSweep};
+
+fn assert_close((slope, intercept): (f64, f64), expected: (f64, f64)) {
+    assert!(
+        (slope - expected.0).abs() < 1e-9 && (intercept - expected.1).abs() < 1e-9,
+        "fit {} dB/dB + {}, expected {} dB/dB + {}",
+        slope,
+        intercept,
+        expected.0,
+        expected.1
+    );
+}
+
+#[test]
+fn fits_points_on_a_line() {
+    let points = [(12.0, -60.0), (14.0, -58.0), (16.0, -56.0), (18.0, -54.0)];
+    assert_close(fit(&points).unwrap(), (1.0, -72.0));
+}
+
+#[test]
+fn fits_scattered_points_by_least_squares() {
+    // y = 0.5x + 1 off by +1, -1, -1, +1: the errors cancel out
+    let points = [(0.0, 2.0), (2.0, 1.0), (4.0, 2.0), (6.0, 5.0)];
+    assert_close(fit(&points).unwrap(), (0.5, 1.0));
+
+    // a compressing PA flattens the slope
+    let points = [(12.0, -60.0), (16.0, -56.0), (20.0, -54.0), (24.0, -53.0)];
+    assert_close(fit(&points).unwrap(), (0.575, -66.1));
+}
+
+#[test]
+fn needs_two_powers() {
+    assert_eq!(fit(&[]), None);
+    assert_eq!(fit(&[(12.0, -60.0)]), None);
+    assert_eq!(fit(&[(12.0, -60.0), (12.0, -58.0)]), None);
+}
+
+#[test]
+fn linear_response_passes() {
+    let rssi = vec![(12, Some(-60.0)), (14, Some(-58.0)), (16, Some(-56.0))];
+    let power_sweep = PowerSweep::from_rssi(rssi, 2.0, 0.2);
+    assert!(power_sweep.passed);
+    assert!(!power_sweep.slope_flagged);
+    assert!(power_sweep.steps.iter().all(|step| !step.flagged));
+}
+
+#[test]
+fn slope_away_from_one_fails_within_the_deviation() {
+    // 0.7 dB/dB: no step is more than 1.2 dB off the 1 dB/dB line
+    let rssi = vec![
+        (12, Some(-60.0)),
+        (14, Some(-58.6)),
+        (16, Some(-57.2)),
+        (18, Some(-55.8)),
+        (20, Some(-54.4)),
+    ];
+    let power_sweep = PowerSweep::from_rssi(rssi, 2.0, 0.2);
+    assert!(power_sweep.steps.iter().all(|step| !step.flagged));
+    assert!(power_sweep.slope_flagged);
+    assert!(!power_sweep.passed);
+    assert!(power_sweep.summary().ends_with("FLAGGED"));
+
+    // unless the slope is let that far
+    let rssi = power_sweep
+        .steps
+        .iter()
+        .map(|step| (step.power, step.rssi))
+        .collect();
+    assert!(PowerSweep::from_rssi(rssi, 2.0, 0.3).passed);
+}
+
+#[test]
+fn no_slope_fails() {
+    let rssi = vec![(12, Some(-60.0)), (14, None)];
+    let power_sweep = PowerSweep::from_rssi(rssi, 2.0, 0.2);
+    assert_eq!(power_sweep.slope, None);
+    assert!(power_sweep.slope_flagged);
+    assert!(!power_sweep.passed);
+}
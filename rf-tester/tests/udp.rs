@@ -0,0 +1,186 @@
+//! The Semtech UDP server, spoken to as a packet forwarder would. Each test
+//! binds its own server to port 0 so that they can run concurrently
+use rf_tester::{udp, wire};
+use semtech_udp::{
+    pull_data, pull_resp::TxPk, server_runtime::Error, server_runtime::Event, tx_ack, MacAddress,
+    Packet, SerializablePacket, StringOrNum, Up,
+};
+use std::net::SocketAddr;
+use tokio::{
+    net::UdpSocket,
+    time::{self, Duration},
+};
+
+const MAC: u64 = 0x0000_0000_0000_00AA;
+
+fn mac() -> MacAddress {
+    MacAddress::new(&MAC.to_be_bytes())
+}
+
+async fn gateway(port: u16) -> UdpSocket {
+    let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .await
+        .unwrap();
+    socket
+        .connect(SocketAddr::from(([127, 0, 0, 1], port)))
+        .await
+        .unwrap();
+    socket
+}
+
+async fn send(socket: &mut UdpSocket, packet: Packet) {
+    let mut buf = vec![0u8; 1024];
+    let n = packet.serialize(&mut buf).unwrap() as usize;
+    socket.send(&buf[..n]).await.unwrap();
+}
+
+async fn recv(socket: &mut UdpSocket) -> Vec<u8> {
+    let mut buf = vec![0u8; 65_536];
+    let n = time::timeout(Duration::from_secs(1), socket.recv(&mut buf))
+        .await
+        .expect("the server should answer")
+        .unwrap();
+    buf[..n].to_vec()
+}
+
+async fn pull_data(socket: &mut UdpSocket) {
+    let pull_data = pull_data::Packet {
+        random_token: 0x1234,
+        gateway_mac: mac(),
+    };
+    send(socket, pull_data.into()).await;
+}
+
+fn txpk() -> TxPk {
+    serde_json::from_value(serde_json::json!({
+        "imme": true, "tmst": 0, "freq": 868.1, "rfch": 0, "powe": 14,
+        "modu": "LORA", "datr": "SF7BW125", "codr": "4/5", "ipol": false,
+        "size": 4, "data": "AQIDBA=="
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn pull_data_is_acknowledged_and_admits_the_gateway() {
+    let (mut uplinks, _downlinks) = udp::bind(0).await.unwrap();
+    let mut socket = gateway(uplinks.port()).await;
+
+    pull_data(&mut socket).await;
+    let (frame, events) = uplinks.recv().await;
+
+    assert_eq!(frame.len(), 12);
+    assert_eq!(recv(&mut socket).await, [2, 0x12, 0x34, 4]);
+    assert!(matches!(events[0], Event::RawPacket(Up::PullData(_))));
+    let addr = socket.local_addr().unwrap();
+    assert!(
+        matches!(events[1], Event::NewClient((client, from)) if client == mac() && from == addr)
+    );
+    assert_eq!(events.len(), 2);
+
+    // the same gateway again is not news
+    pull_data(&mut socket).await;
+    assert_eq!(uplinks.recv().await.1.len(), 1);
+}
+
+#[tokio::test]
+async fn push_data_semtech_udp_cannot_parse_is_acknowledged() {
+    let (mut uplinks, _downlinks) = udp::bind(0).await.unwrap();
+    let mut socket = gateway(uplinks.port()).await;
+
+    // a failed CRC, and more than the 1024 bytes semtech-udp reads at once
+    let rxpk = serde_json::json!({
+        "tmst": 1, "chan": 0, "rfch": 0, "freq": 868.1, "stat": -1,
+        "modu": "LORA", "datr": "SF7BW125", "codr": "4/5", "rssi": -60,
+        "lsnr": 9.5, "size": 4, "data": "AQIDBA=="
+    });
+    let data = serde_json::json!({ "rxpk": vec![rxpk; 8] });
+    let mut frame = vec![2, 0x56, 0x78, 0];
+    frame.extend_from_slice(&MAC.to_be_bytes());
+    frame.extend_from_slice(data.to_string().as_bytes());
+    assert!(frame.len() > 1024);
+    socket.send(&frame).await.unwrap();
+
+    let (received, events) = uplinks.recv().await;
+    assert_eq!(received, frame);
+    assert_eq!(recv(&mut socket).await, [2, 0x56, 0x78, 1]);
+    match &events[..] {
+        [Event::UnableToParseUdpFrame(received)] => assert_eq!(received, &frame),
+        events => panic!("unexpected events {:?}", events),
+    }
+}
+
+#[tokio::test]
+async fn downlink_to_an_unknown_gateway_fails_at_once() {
+    let (_uplinks, downlinks) = udp::bind(0).await.unwrap();
+    let frame = wire::pull_resp(1, &txpk().into());
+
+    let sent = time::timeout(
+        Duration::from_millis(100),
+        downlinks.send(&mac(), 1, &frame, Duration::from_secs(5)),
+    )
+    .await
+    .expect("no waiting for a gateway which is not there");
+    assert!(matches!(sent, Err(Error::UnknownMac)));
+}
+
+#[tokio::test]
+async fn downlink_goes_as_written_and_waits_for_its_tx_ack() {
+    let (mut uplinks, downlinks) = udp::bind(0).await.unwrap();
+    let mut socket = gateway(uplinks.port()).await;
+    pull_data(&mut socket).await;
+    uplinks.recv().await;
+    recv(&mut socket).await;
+    tokio::spawn(async move {
+        loop {
+            uplinks.recv().await;
+        }
+    });
+
+    for (token, error) in [(0xBEEF, None), (0xCAFE, Some(tx_ack::Error::TOO_LATE))] {
+        let frame = wire::pull_resp(token, &txpk().into());
+        let sending = {
+            let downlinks = downlinks.clone();
+            let frame = frame.clone();
+            tokio::spawn(async move {
+                downlinks
+                    .send(&mac(), token, &frame, Duration::from_secs(1))
+                    .await
+            })
+        };
+
+        let received = recv(&mut socket).await;
+        assert_eq!(received, frame);
+        let (received_token, downlink) = wire::parse_pull_resp(&received).unwrap();
+        assert_eq!(received_token, token);
+        assert!(downlink.tmst);
+        assert!(matches!(downlink.txpk.tmst, StringOrNum::N(0)));
+        let tx_ack = tx_ack::Packet {
+            random_token: token,
+            gateway_mac: mac(),
+            data: error.map(tx_ack::TxPkNack::new),
+        };
+        send(&mut socket, tx_ack.into()).await;
+
+        match (sending.await.unwrap(), error) {
+            (Ok(()), None) => (),
+            (Err(Error::AckError(got)), Some(expected)) => {
+                assert_eq!(format!("{:?}", got), format!("{:?}", expected))
+            }
+            (sent, _) => panic!("unexpected outcome {:?}", sent),
+        }
+    }
+}
+
+#[tokio::test]
+async fn unanswered_downlink_times_out() {
+    let (mut uplinks, downlinks) = udp::bind(0).await.unwrap();
+    let mut socket = gateway(uplinks.port()).await;
+    pull_data(&mut socket).await;
+    uplinks.recv().await;
+
+    let frame = wire::pull_resp(7, &txpk().into());
+    let sent = downlinks
+        .send(&mac(), 7, &frame, Duration::from_millis(100))
+        .await;
+    assert!(matches!(sent, Err(Error::SendTimeout)));
+}
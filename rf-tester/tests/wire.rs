@@ -0,0 +1,126 @@
+//! PULL_RESP as packet forwarders read them, and PUSH_DATA as they send them
+use rf_tester::wire::{self, Downlink};
+use semtech_udp::{parser::Parser, pull_resp::TxPk, Packet, StringOrNum, Up};
+use serde_json::{json, Value};
+
+fn txpk(modu: &str, datr: &str) -> TxPk {
+    TxPk {
+        imme: false,
+        tmst: StringOrNum::N(1_000_000),
+        tmms: None,
+        freq: 868.8,
+        rfch: 0,
+        powe: 14,
+        modu: modu.into(),
+        datr: datr.into(),
+        codr: "4/5".into(),
+        fdev: None,
+        ipol: false,
+        prea: None,
+        size: 4,
+        data: "AAAAAA==".into(),
+        ncrc: None,
+    }
+}
+
+// the txpk object of a PULL_RESP
+fn fields(frame: &[u8]) -> Value {
+    let data: Value = serde_json::from_slice(&frame[4..]).unwrap();
+    data["txpk"].clone()
+}
+
+#[test]
+fn pull_resp_carries_the_token() {
+    let frame = wire::pull_resp(0x1234, &txpk("LORA", "SF7BW125").into());
+
+    assert_eq!(frame[..4], [2, 0x12, 0x34, 3]);
+    let (token, downlink) = wire::parse_pull_resp(&frame).unwrap();
+    assert_eq!(token, 0x1234);
+    assert!(downlink.tmst);
+    assert!(!downlink.implicit_header);
+    assert_eq!(downlink.txpk.datr, "SF7BW125");
+}
+
+#[test]
+fn gps_time_goes_without_tmst() {
+    let mut txpk = txpk("LORA", "SF9BW125");
+    txpk.tmms = Some(StringOrNum::N(1_300_000_000_000));
+    let downlink = Downlink {
+        implicit_header: true,
+        ..txpk.into()
+    };
+    let frame = wire::pull_resp(1, &downlink);
+
+    let fields = fields(&frame);
+    assert!(fields.get("tmst").is_none());
+    assert_eq!(fields["tmms"], 1_300_000_000_000u64);
+    assert_eq!(fields["nhdr"], true);
+    let (_, downlink) = wire::parse_pull_resp(&frame).unwrap();
+    assert!(!downlink.tmst);
+    assert!(downlink.implicit_header);
+}
+
+#[test]
+fn fsk_bitrate_is_a_number() {
+    let frame = wire::pull_resp(1, &txpk("FSK", "50000").into());
+
+    assert_eq!(fields(&frame)["datr"], 50_000);
+    let (_, downlink) = wire::parse_pull_resp(&frame).unwrap();
+    assert_eq!(downlink.txpk.datr, "50000");
+}
+
+#[test]
+fn fsk_bitrate_as_a_string_reads_as_zero() {
+    let mut frame = vec![2, 0, 1, 3];
+    let txpk = json!({ "txpk": txpk("FSK", "50000") });
+    frame.extend_from_slice(txpk.to_string().as_bytes());
+
+    let (_, downlink) = wire::parse_pull_resp(&frame).unwrap();
+    assert_eq!(downlink.txpk.datr, "0");
+}
+
+// a PUSH_DATA from gateway 1 carrying `rxpk`
+fn push_data(rxpk: Value) -> Vec<u8> {
+    let mut frame = vec![2, 0x12, 0x34, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    frame.extend_from_slice(json!({ "rxpk": [rxpk] }).to_string().as_bytes());
+    frame
+}
+
+fn parsed(frame: &[u8]) -> semtech_udp::push_data::RxPk {
+    match Packet::parse(frame) {
+        Ok(Packet::Up(Up::PushData(push_data))) => push_data.data.rxpk.unwrap().remove(0),
+        _ => panic!("not a PUSH_DATA"),
+    }
+}
+
+#[test]
+fn foff_of_a_v1_rxpk() {
+    // as sx1302_hal's lora_pkt_fwd reports it
+    let frame = push_data(json!({
+        "tmst": 3_512_348_611u64, "chan": 2, "rfch": 0, "freq": 866.349812, "stat": 1,
+        "modu": "LORA", "datr": "SF7BW125", "codr": "4/6", "rssi": -35, "lsnr": 5.1,
+        "foff": -1250, "size": 4, "data": "AAAAAA=="
+    }));
+    assert_eq!(wire::foff(&frame, &parsed(&frame)), Some(-1250));
+}
+
+#[test]
+fn foff_of_a_v2_rxpk() {
+    let frame = push_data(json!({
+            "jver": 1, "tmst": 3_512_348_611u64, "brd": 0, "aesk": 0, "freq": 866.349812,
+            "stat": 1, "modu": "LORA", "datr": "SF7BW125", "codr": "4/6", "size": 4,
+            "data": "AAAAAA==",
+            "rsig": [{ "ant": 0, "chan": 2, "rssic": -35, "lsnr": 5.1, "foff": 830 }]
+    }));
+    assert_eq!(wire::foff(&frame, &parsed(&frame)), Some(830));
+}
+
+#[test]
+fn sx1301_reports_no_foff() {
+    let frame = push_data(json!({
+        "tmst": 3_512_348_611u64, "chan": 2, "rfch": 0, "freq": 866.349812, "stat": 1,
+        "modu": "LORA", "datr": "SF7BW125", "codr": "4/6", "rssi": -35, "lsnr": 5.1,
+        "size": 4, "data": "AAAAAA=="
+    }));
+    assert_eq!(wire::foff(&frame, &parsed(&frame)), None);
+}
@@ -0,0 +1,182 @@
+use super::error::Result;
+use super::global_conf::{Config, Sx130xConfData};
+use serde::Serialize;
+use std::fmt;
+
+/// Effective channel plan of a configuration: one row per channel slot of
+/// every concentrator, disabled slots included
+#[derive(Serialize, Debug)]
+pub struct ChannelPlan {
+    pub channels: Vec<PlannedChannel>,
+}
+
+/// A row of the [`ChannelPlan`]
+#[derive(Serialize, Debug)]
+pub struct PlannedChannel {
+    pub concentrator: usize,
+    /// index of the slot among channels of the same kind
+    pub slot: usize,
+    pub kind: ChannelKind,
+    pub radio: Option<usize>,
+    #[serde(rename = "if")]
+    pub r#if: Option<isize>,
+    /// radio frequency + IF, in Hz
+    pub freq: Option<isize>,
+    pub bandwidth: Option<usize>,
+    pub enabled: bool,
+}
+
+/// Which kind of demodulator a channel slot belongs to
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ChannelKind {
+    #[serde(rename = "multiSF")]
+    MultiSf,
+    #[serde(rename = "std")]
+    LoraStd,
+    #[serde(rename = "FSK")]
+    Fsk,
+}
+
+impl fmt::Display for ChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            ChannelKind::MultiSf => "multiSF",
+            ChannelKind::LoraStd => "std",
+            ChannelKind::Fsk => "FSK",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+const CSV_HEADER: &str = "concentrator,slot,kind,radio,if,freq,bandwidth,enabled";
+
+impl ChannelPlan {
+    pub fn from_config(config: &Config) -> Result<ChannelPlan> {
+        let mut channels = Vec::new();
+        for (index, concentrator) in config.concentrators().iter().enumerate() {
+            channels.extend(planned_channels(index, concentrator)?);
+        }
+        Ok(ChannelPlan { channels })
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(CSV_HEADER);
+        for channel in &self.channels {
+            csv.push_str(&format!(
+                "\n{},{},{},{},{},{},{},{}",
+                channel.concentrator,
+                channel.slot,
+                channel.kind,
+                or_empty(channel.radio),
+                or_empty(channel.r#if),
+                or_empty(channel.freq),
+                or_empty(channel.bandwidth),
+                channel.enabled
+            ));
+        }
+        csv
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from(
+            "| Concentrator | Slot | Kind | Radio | IF (Hz) | Frequency (MHz) | Bandwidth (kHz) | Enabled |\n\
+             |---|---|---|---|---|---|---|---|",
+        );
+        for channel in &self.channels {
+            markdown.push_str(&format!(
+                "\n| {} | {} | {} | {} | {} | {} | {} | {} |",
+                channel.concentrator,
+                channel.slot,
+                channel.kind,
+                or_empty(channel.radio),
+                or_empty(channel.r#if),
+                or_empty(channel.freq.map(|freq| freq as f64 / 1_000_000.0)),
+                or_empty(
+                    channel
+                        .bandwidth
+                        .map(|bandwidth| bandwidth as f64 / 1_000.0)
+                ),
+                if channel.enabled { "yes" } else { "no" }
+            ));
+        }
+        markdown
+    }
+
+    /// Human readable listing of a single concentrator, as printed by default
+    pub fn to_text(&self, concentrator: usize) -> String {
+        self.channels
+            .iter()
+            .filter(|channel| channel.concentrator == concentrator)
+            .map(|channel| {
+                let label = match channel.kind {
+                    ChannelKind::MultiSf => format!("{:<9}", channel.slot + 1),
+                    ChannelKind::LoraStd => "Fat LoRa ".to_string(),
+                    ChannelKind::Fsk => "FSK      ".to_string(),
+                };
+                let description = match (channel.kind, channel.freq, channel.bandwidth) {
+                    (ChannelKind::MultiSf, Some(freq), _) | (_, Some(freq), None) => {
+                        format!("{} MHz", freq as f64 / 1_000_000.0)
+                    }
+                    (_, Some(freq), Some(bandwidth)) => format!(
+                        "{} MHz, BW {} KHz",
+                        freq as f64 / 1_000_000.0,
+                        bandwidth as f64 / 1_000.0
+                    ),
+                    _ => "Disabled".to_string(),
+                };
+                label + &description
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+fn or_empty<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn planned_channels(index: usize, concentrator: &Sx130xConfData) -> Result<Vec<PlannedChannel>> {
+    let radios = &concentrator.radios;
+    let mut channels = concentrator
+        .multi_sf
+        .iter()
+        .enumerate()
+        .map(|(slot, channel)| {
+            Ok(PlannedChannel {
+                concentrator: index,
+                slot,
+                kind: ChannelKind::MultiSf,
+                radio: channel.config.as_ref().map(|config| config.radio),
+                r#if: channel.config.as_ref().map(|config| config.r#if),
+                freq: channel.frequency(radios)?,
+                bandwidth: Some(125_000).filter(|_| channel.enable),
+                enabled: channel.enable,
+            })
+        })
+        .collect::<Result<Vec<PlannedChannel>>>()?;
+    channels.push(PlannedChannel {
+        concentrator: index,
+        slot: 0,
+        kind: ChannelKind::LoraStd,
+        radio: concentrator.lora_std.config.as_ref().map(|c| c.radio),
+        r#if: concentrator.lora_std.config.as_ref().map(|c| c.r#if),
+        freq: concentrator.lora_std.frequency(radios)?,
+        bandwidth: concentrator.lora_std.bandwidth()?,
+        enabled: concentrator.lora_std.enable,
+    });
+    channels.push(PlannedChannel {
+        concentrator: index,
+        slot: 0,
+        kind: ChannelKind::Fsk,
+        radio: concentrator.fsk.config.as_ref().map(|c| c.radio),
+        r#if: concentrator.fsk.config.as_ref().map(|c| c.r#if),
+        freq: concentrator.fsk.frequency(radios)?,
+        bandwidth: concentrator.fsk.bandwidth()?,
+        enabled: concentrator.fsk.enable,
+    });
+    Ok(channels)
+}
@@ -0,0 +1,223 @@
+use super::error::{Error, Result};
+use super::global_conf::*;
+use serde::Deserialize;
+use serde_json::Map;
+use std::{io::Read, str::FromStr};
+
+// Usable IF bandwidth of a radio for a channel of the given bandwidth.
+// These mirror LGW_RF_RX_BANDWIDTH_{125,250,500}KHZ in the Semtech HAL
+const fn radio_bandwidth(channel_bandwidth: usize) -> isize {
+    match channel_bandwidth {
+        0..=125_000 => 925_000,
+        125_001..=250_000 => 1_000_000,
+        _ => 1_100_000,
+    }
+}
+
+/// ChirpStack Concentratord configuration.
+///
+/// Concentratord only describes channels by their absolute frequency; the
+/// radio centre frequencies are derived from the channel plan.
+/// Everything else in the file (model, logging, ZMQ endpoints...) is ignored
+#[derive(Deserialize, Debug)]
+pub struct ConcentratordConf {
+    gateway: Gateway,
+}
+
+#[derive(Deserialize, Debug)]
+struct Gateway {
+    concentrator: Concentrator,
+}
+
+#[derive(Deserialize, Debug)]
+struct Concentrator {
+    #[serde(default)]
+    multi_sf_channels: Vec<isize>,
+    lora_std: Option<SingleChannel>,
+    fsk: Option<SingleChannel>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SingleChannel {
+    frequency: isize,
+    bandwidth: usize,
+}
+
+impl ConcentratordConf {
+    /// Reads a TOML file and converts it, see [`ConcentratordConf::into_sx130x_conf`].
+    /// The TX range of the radios comes with the gateway model rather than
+    /// the file, so the summary does not note it as missing
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Config> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        let concentratord: ConcentratordConf = contents.parse()?;
+        let mut config = Config::from_sx130x_conf(concentratord.into_sx130x_conf()?);
+        config.tx_range_elsewhere = true;
+        Ok(config)
+    }
+
+    /// Assigns every channel to one of the two radios and converts the
+    /// channel plan to the radio/IF representation used by global_conf.json
+    pub fn into_sx130x_conf(self) -> Result<Sx130xConfData> {
+        let concentrator = self.gateway.concentrator;
+        if concentrator.multi_sf_channels.len() > 8 {
+            return Err(Error::InvalidChannelPlan(format!(
+                "{} multi-SF channels configured but SX130x only has 8",
+                concentrator.multi_sf_channels.len()
+            )));
+        }
+
+        // (frequency, bandwidth) of everything which needs a radio
+        let mut multi_sf_channels: Vec<(isize, usize)> = concentrator
+            .multi_sf_channels
+            .iter()
+            .map(|frequency| (*frequency, 125_000))
+            .collect();
+        multi_sf_channels.sort_unstable();
+        let single_channels: Vec<(isize, usize)> = concentrator
+            .lora_std
+            .iter()
+            .chain(concentrator.fsk.iter())
+            .filter(|single| single.frequency != 0)
+            .map(|single| (single.frequency, single.bandwidth))
+            .collect();
+
+        // split the sorted multi-SF channels between the radios, preferring an
+        // even split, and find a split where the single channels also fit
+        let count = multi_sf_channels.len();
+        let mut splits: Vec<usize> = (0..=count).collect();
+        splits.sort_by_key(|split| (*split as isize - (count as isize + 1) / 2).abs());
+        let (centers, radios) = splits
+            .into_iter()
+            .find_map(|split| {
+                let mut radios = [
+                    multi_sf_channels[..split].to_vec(),
+                    multi_sf_channels[split..].to_vec(),
+                ];
+                for channel in &single_channels {
+                    let radio = radios.iter_mut().find(|radio| {
+                        let mut candidate = radio.to_vec();
+                        candidate.push(*channel);
+                        center(&candidate).is_some()
+                    })?;
+                    radio.push(*channel);
+                }
+                Some(([center(&radios[0])?, center(&radios[1])?], radios))
+            })
+            .ok_or_else(|| {
+                Error::InvalidChannelPlan(
+                    "channels do not fit within the bandwidth of the two radios".to_string(),
+                )
+            })?;
+
+        let radio_index = |frequency: isize, bandwidth: usize| -> usize {
+            if radios[0].contains(&(frequency, bandwidth)) {
+                0
+            } else {
+                1
+            }
+        };
+        let multi_sf = |index: usize| -> Channel {
+            match concentrator.multi_sf_channels.get(index) {
+                Some(&frequency) => {
+                    let radio = radio_index(frequency, 125_000);
+                    Channel {
+                        enable: true,
+                        config: Some(ChannelEnabled {
+                            r#if: frequency - centers[radio],
+                            radio,
+                        }),
+                        other: Map::new(),
+                    }
+                }
+                None => Channel {
+                    enable: false,
+                    config: None,
+                    other: Map::new(),
+                },
+            }
+        };
+        let single = |channel: &Option<SingleChannel>| -> Option<LoraStdEnabled> {
+            match channel {
+                Some(channel) if channel.frequency != 0 => {
+                    let radio = radio_index(channel.frequency, channel.bandwidth);
+                    Some(LoraStdEnabled {
+                        bandwidth: channel.bandwidth,
+                        r#if: channel.frequency - centers[radio],
+                        radio,
+                    })
+                }
+                _ => None,
+            }
+        };
+        let lora_std = single(&concentrator.lora_std);
+        let fsk = single(&concentrator.fsk);
+
+        Ok(Sx130xConfData {
+            radios: centers
+                .iter()
+                .zip(&radios)
+                .map(|(center, channels)| {
+                    let enable = !channels.is_empty();
+                    let mut other = Map::new();
+                    other.insert("enable".to_string(), enable.into());
+                    Radio {
+                        freq: Some(*center).filter(|_| enable),
+                        tx_freq_min: None,
+                        tx_freq_max: None,
+                        other,
+                    }
+                })
+                .collect(),
+            multi_sf: (0..8).map(multi_sf).collect(),
+            lora_std: LoraStd {
+                enable: lora_std.is_some(),
+                config: lora_std,
+                other: Map::new(),
+            },
+            fsk: ChannelFsk {
+                enable: fsk.is_some(),
+                config: fsk.map(|fsk| FskEnabled {
+                    bandwidth: Some(fsk.bandwidth),
+                    r#if: fsk.r#if,
+                    radio: fsk.radio,
+                }),
+                other: Map::new(),
+            },
+            lora_std_listed: true,
+            fsk_listed: true,
+            other: Map::new(),
+        })
+    }
+}
+
+impl FromStr for ConcentratordConf {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<ConcentratordConf> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+// Each channel constrains the radio centre to within its allowed IF offset;
+// picks the middle of the range every channel agrees on, or None if there is
+// no such range. Anything fits an unused radio, which is left disabled
+fn center(channels: &[(isize, usize)]) -> Option<isize> {
+    if channels.is_empty() {
+        return Some(0);
+    }
+    let max_offset = |bandwidth: usize| (radio_bandwidth(bandwidth) - bandwidth as isize) / 2;
+    let lower = channels
+        .iter()
+        .map(|(frequency, bandwidth)| frequency - max_offset(*bandwidth))
+        .max()?;
+    let upper = channels
+        .iter()
+        .map(|(frequency, bandwidth)| frequency + max_offset(*bandwidth))
+        .min()?;
+    if lower <= upper {
+        Some((lower + upper) / 2)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Toml(toml::de::Error),
+    /// The channels can not be mapped onto the concentrator's radios
+    InvalidChannelPlan(String),
+    /// An enabled channel can not be placed on a radio
+    InvalidChannel(String),
+    /// A comment or variable is never closed
+    Decomment(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::Toml(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "IO error: {}", err),
+            Error::Json(err) => write!(f, "JSON error: {}", err),
+            Error::Toml(err) => write!(f, "TOML error: {}", err),
+            Error::InvalidChannelPlan(msg) => write!(f, "Invalid channel plan: {}", msg),
+            Error::InvalidChannel(msg) => write!(f, "Invalid channel: {}", msg),
+            Error::Decomment(msg) => write!(f, "Comment error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Toml(err) => Some(err),
+            Error::InvalidChannelPlan(_) | Error::InvalidChannel(_) | Error::Decomment(_) => None,
+        }
+    }
+}
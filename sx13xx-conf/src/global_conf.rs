@@ -1,365 +1,587 @@
-use super::File;
-use serde::{Deserialize, Serialize};
-use std::io::prelude::*;
-
-// Top level struct allows for the "gateway_conf" field to exist
-// without getting in the way of the flexible parsing of
-// SX130x_conf or SX1301_conf
-#[derive(Deserialize, Serialize)]
+use super::channel_plan::ChannelPlan;
+use super::error;
+use super::validation::{self, Issue};
+use regions::Region;
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+/// A concentrator configuration file or message.
+///
+/// Top level struct allows for the "gateway_conf" field to exist
+/// without getting in the way of the flexible parsing of
+/// SX130x_conf or SX1301_conf. Fields which are not part of the
+/// channel plan are kept as-is so that [`Config::to_writer`] gives
+/// back an equivalent file
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     #[serde(flatten)]
     config: Sx130xConf,
+    // only present in Basic Station router_config messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    upchannels: Vec<UpChannel>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+    // the radios' TX range is not part of the file (eg: Concentratord)
+    #[serde(skip)]
+    pub(crate) tx_range_elsewhere: bool,
 }
 
 // This enum allows Sx1301/Sx1302 files to be parsed flexibly
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Debug)]
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 enum Sx130xConf {
-    SX130x_conf(Sx130xConfData),
-    SX1301_conf(Sx130xConfData),
+    SX130x_conf(Concentrators),
+    SX1301_conf(Concentrators),
+    // Basic Station station.conf
+    radio_conf(Concentrators),
+    // Basic Station router_config
+    sx1301_conf(Concentrators),
 }
 
+// Multi-board gateways (eg: mp_pkt_fwd, Basic Station with "sx1301/2")
+// give an array with one entry per concentrator
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+enum Concentrators {
+    One(Sx130xConfData),
+    Many(Vec<Sx130xConfData>),
+}
+
+/// Basic Station upchannels entry: [frequency, min DR, max DR]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct UpChannel(pub isize, pub u8, pub u8);
+
 impl Config {
-    pub fn summary(&self) -> String {
-        match &self.config {
-            Sx130xConf::SX1301_conf(sx1301) => sx1301.summary(),
-            Sx130xConf::SX130x_conf(sx1302) => sx1302.summary(),
+    /// Text rendering of the channel plan, followed by any warnings
+    pub fn summary(&self) -> error::Result<String> {
+        let plan = ChannelPlan::from_config(self)?;
+        let concentrators = self.concentrators();
+        let mut summary = concentrators
+            .iter()
+            .enumerate()
+            .map(|(index, concentrator)| {
+                let text = plan.to_text(index) + &concentrator.warnings(self.tx_range_elsewhere)?;
+                Ok(if concentrators.len() == 1 {
+                    text
+                } else {
+                    format!("Concentrator {}\n{}", index, text)
+                })
+            })
+            .collect::<error::Result<Vec<String>>>()?
+            .join("\n\n");
+
+        // the LNS lists the frequencies it expects to hear on, so make sure
+        // the concentrator is actually tuned to each of them
+        let frequencies = self.frequencies()?;
+        for UpChannel(frequency, min_dr, max_dr) in &self.upchannels {
+            if !frequencies.contains(frequency) {
+                summary.push_str(&format!(
+                    "\nWARNING: upchannel {} MHz (DR{}-{}) is not served by any channel",
+                    *frequency as f64 / 1_000_000.0,
+                    min_dr,
+                    max_dr
+                ));
+            }
         }
+        Ok(summary)
     }
 
-    pub fn frequency(&self, channel: usize) -> Option<isize> {
-        match &self.config {
-            Sx130xConf::SX1301_conf(sx1301) => sx1301.frequency(channel),
-            Sx130xConf::SX130x_conf(sx1302) => sx1302.frequency(channel),
+    /// One entry per concentrator; most gateways have a single one
+    pub fn concentrators(&self) -> &[Sx130xConfData] {
+        let concentrators = match &self.config {
+            Sx130xConf::SX130x_conf(concentrators)
+            | Sx130xConf::SX1301_conf(concentrators)
+            | Sx130xConf::radio_conf(concentrators)
+            | Sx130xConf::sx1301_conf(concentrators) => concentrators,
+        };
+        match concentrators {
+            Concentrators::One(concentrator) => std::slice::from_ref(concentrator),
+            Concentrators::Many(concentrators) => concentrators,
+        }
+    }
+
+    /// Every LoRa frequency listened on, across all concentrators
+    pub fn frequencies(&self) -> error::Result<Vec<isize>> {
+        let mut frequencies = Vec::new();
+        for concentrator in self.concentrators() {
+            frequencies.extend(concentrator.frequencies()?);
+        }
+        Ok(frequencies)
+    }
+
+    /// Frequencies the LNS expects to hear on. Only set for Basic Station
+    /// router_config messages
+    pub fn upchannels(&self) -> &[UpChannel] {
+        &self.upchannels
+    }
+
+    pub fn channel_plan(&self) -> error::Result<ChannelPlan> {
+        ChannelPlan::from_config(self)
+    }
+
+    /// Checks the configuration against a region, see [`validation::validate`]
+    pub fn validate(&self, region: &Region) -> error::Result<Vec<Issue>> {
+        validation::validate(self, region)
+    }
+
+    /// Wraps a single concentrator into an "SX130x_conf" configuration
+    pub fn from_sx130x_conf(config: Sx130xConfData) -> Config {
+        Config {
+            config: Sx130xConf::SX130x_conf(Concentrators::One(config)),
+            upchannels: Vec::new(),
+            other: Map::new(),
+            tx_range_elsewhere: false,
         }
     }
 
-    pub fn from_file(mut file: File) -> Result<Config, Box<dyn std::error::Error>> {
+    /// Reads a JSON configuration. Comments (eg: "//" or "/* */") and
+    /// variables (eg: ${VAR}) are stripped out before parsing
+    pub fn from_reader<R: Read>(mut reader: R) -> error::Result<Config> {
         let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        let decommented_content = decomment(&contents);
-        let config = serde_json::from_str(&decommented_content)?;
-        Ok(config)
+        reader.read_to_string(&mut contents)?;
+        contents.parse()
+    }
+
+    /// Writes the configuration back as pretty printed JSON
+    pub fn to_writer<W: Write>(&self, writer: W) -> error::Result<()> {
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+}
+
+impl FromStr for Config {
+    type Err = error::Error;
+
+    fn from_str(contents: &str) -> error::Result<Config> {
+        let decommented_content = decomment(contents)?;
+        Ok(serde_json::from_str(&decommented_content)?)
     }
 }
 
-/// Removes both c-style block comments and c++-style line comments from a str.
-pub fn decomment(src: &str) -> String {
+/// Removes both c-style block comments and c++-style line comments from a str,
+/// and replaces variables (eg: ${VAR}) with an empty string. Fails on a
+/// comment or variable which is never closed
+pub fn decomment(src: &str) -> error::Result<String> {
     let mut in_line_comment = false;
     let mut in_block_comment = false;
+    let mut in_string = false;
     let mut variable = false;
     let mut decommented = String::with_capacity(src.len());
     let mut itr = src.chars().peekable();
     while let Some(ch) = itr.next() {
+        if in_line_comment {
+            in_line_comment = ch != '\n';
+            continue;
+        }
+        if in_block_comment {
+            if ch == '*' && itr.peek() == Some(&'/') {
+                let _ = itr.next();
+                in_block_comment = false;
+            }
+            continue;
+        }
+        if variable {
+            if ch == '}' {
+                decommented.push_str("\"\"");
+                variable = false;
+            }
+            continue;
+        }
+        if in_string {
+            // "//" in a URL is not a comment
+            match ch {
+                '\\' => {
+                    decommented.push(ch);
+                    decommented.extend(itr.next());
+                    continue;
+                }
+                '"' => in_string = false,
+                _ => (),
+            }
+            decommented.push(ch);
+            continue;
+        }
         match (ch, itr.peek()) {
             ('$', Some('{')) => {
-                assert!(!in_line_comment);
-                assert!(!in_block_comment);
                 let _ = itr.next();
                 variable = true;
             }
-            (_, Some('}')) => {
-                if variable {
-                    assert!(!in_line_comment);
-                    assert!(!in_block_comment);
-                    decommented.push_str("\"\"");
-                    let _ = itr.next();
-                    variable = false;
-                } else if !in_block_comment && !in_line_comment && !ch.is_whitespace() {
-                    decommented.push(ch)
-                }
-            }
             ('/', Some('*')) => {
-                assert!(!in_line_comment);
-                assert!(!in_block_comment);
                 let _ = itr.next();
                 in_block_comment = true;
             }
-            ('*', Some('/')) => {
-                assert!(in_block_comment);
-                let _ = itr.next();
-                in_block_comment = false;
-            }
             ('/', Some('/')) => {
-                assert!(!in_line_comment);
-                assert!(!in_block_comment);
                 let _ = itr.next();
                 in_line_comment = true;
             }
-            ('\r', Some('\n')) if in_line_comment => {
-                let _ = itr.next();
-                in_line_comment = false;
+            ('"', _) => {
+                in_string = true;
+                decommented.push(ch);
             }
-            ('\n', _) if in_line_comment => {
-                in_line_comment = false;
-            }
-            _ if in_block_comment || in_line_comment || variable => (),
             _ => decommented.push(ch),
         }
     }
-    assert!(!in_block_comment);
-    decommented
+    if in_block_comment {
+        return Err(error::Error::Decomment(
+            "block comment is never closed".to_string(),
+        ));
+    }
+    if variable {
+        return Err(error::Error::Decomment(
+            "variable is never closed".to_string(),
+        ));
+    }
+    Ok(decommented)
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-#[allow(non_snake_case)]
-struct Sx130xConfData {
-    radio_0: Radio,
-    radio_1: Radio,
-    chan_multiSF_0: Channel,
-    chan_multiSF_1: Channel,
-    chan_multiSF_2: Channel,
-    chan_multiSF_3: Channel,
-    chan_multiSF_4: Channel,
-    chan_multiSF_5: Channel,
-    chan_multiSF_6: Channel,
-    chan_multiSF_7: Channel,
-    chan_Lora_std: LoraStd,
-    chan_FSK: ChannelFsk,
+/// Configuration of a single concentrator. The number of radios and of
+/// multi-SF channels is taken from the "radio_N" and "chan_multiSF_N" keys
+/// present in the file rather than being fixed to 2 and 8
+#[derive(Debug)]
+pub struct Sx130xConfData {
+    /// "radio_0", "radio_1"...
+    pub radios: Vec<Radio>,
+    /// "chan_multiSF_0", "chan_multiSF_1"...
+    pub multi_sf: Vec<Channel>,
+    /// "chan_Lora_std", disabled when missing (eg: Basic Station station.conf)
+    pub lora_std: LoraStd,
+    /// "chan_FSK", disabled when missing
+    pub fsk: ChannelFsk,
+    // whether "chan_Lora_std" and "chan_FSK" were in the file, so that a
+    // disabled channel is only written back where it was read from
+    pub(crate) lora_std_listed: bool,
+    pub(crate) fsk_listed: bool,
+    /// Everything else (eg: "lorawan_public", "clksrc", "tx_lut_0"...)
+    pub other: Map<String, Value>,
 }
 
 impl Sx130xConfData {
-    fn frequency(&self, channel: usize) -> Option<isize> {
-        match channel {
-            0 => self.chan_multiSF_0.frequency(&self.radio_0, &self.radio_1),
-            1 => self.chan_multiSF_1.frequency(&self.radio_0, &self.radio_1),
-            2 => self.chan_multiSF_2.frequency(&self.radio_0, &self.radio_1),
-            3 => self.chan_multiSF_3.frequency(&self.radio_0, &self.radio_1),
-            4 => self.chan_multiSF_4.frequency(&self.radio_0, &self.radio_1),
-            5 => self.chan_multiSF_5.frequency(&self.radio_0, &self.radio_1),
-            6 => self.chan_multiSF_6.frequency(&self.radio_0, &self.radio_1),
-            7 => self.chan_multiSF_7.frequency(&self.radio_0, &self.radio_1),
-            8 => self.chan_Lora_std.frequency(&self.radio_0, &self.radio_1),
-            9 => self.chan_FSK.frequency(&self.radio_0, &self.radio_1),
-            _ => None,
+    /// Frequencies of the enabled multi-SF channels
+    pub fn multi_sf_frequencies(&self) -> error::Result<Vec<isize>> {
+        let mut frequencies = Vec::new();
+        for channel in &self.multi_sf {
+            frequencies.extend(channel.frequency(&self.radios)?);
         }
+        Ok(frequencies)
     }
 
-    fn summary(&self) -> String {
+    /// Frequency of the enabled LoRa standard (fat) channel
+    pub fn lora_std_frequency(&self) -> error::Result<Option<isize>> {
+        self.lora_std.frequency(&self.radios)
+    }
+
+    /// Every LoRa frequency this concentrator listens on
+    pub fn frequencies(&self) -> error::Result<Vec<isize>> {
+        let mut frequencies = self.multi_sf_frequencies()?;
+        frequencies.extend(self.lora_std_frequency()?);
+        Ok(frequencies)
+    }
+
+    fn warnings(&self, tx_range_elsewhere: bool) -> error::Result<String> {
         // We will confirm that all "listened to" frequencies can also be transmitted on
         // since that is a requirement for POC
-        let mut frequencies = Vec::new();
-        if let Some(frequency) = self.chan_multiSF_0.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        let frequencies = self.multi_sf_frequencies()?;
+
+        // iterate through all frequencies and confirm that they are between
+        // tx_freq_min and tx_freq_max. Basic Station does not carry these
+        // limits in station.conf or router_config, so the check is skipped there
+        let mut valid_tx = true;
+        let tx_range = self
+            .radios
+            .first()
+            .map_or((None, None), |radio| (radio.tx_freq_min, radio.tx_freq_max));
+        if let (Some(lb), Some(ub)) = tx_range {
+            for frequency in frequencies {
+                if frequency > ub || frequency < lb {
+                    valid_tx = false;
+                }
+            }
         }
-        if let Some(frequency) = self.chan_multiSF_1.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+
+        let mut warnings = String::new();
+        if !valid_tx {
+            warnings.push_str("\nWARNING: Cannot transmit on all uplink frequencies for POC!");
         }
-        if let Some(frequency) = self.chan_multiSF_2.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        if !tx_range_elsewhere && (tx_range.0.is_none() || tx_range.1.is_none()) {
+            warnings
+                .push_str("\nNOTE: No tx_freq_min/tx_freq_max for radio_0, TX range not checked");
         }
-        if let Some(frequency) = self.chan_multiSF_3.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        Ok(warnings)
+    }
+}
+
+impl Serialize for Sx130xConfData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let lora_std = self.lora_std_listed || self.lora_std.enable;
+        let fsk = self.fsk_listed || self.fsk.enable;
+        let mut map = serializer.serialize_map(Some(
+            self.other.len()
+                + self.radios.len()
+                + self.multi_sf.len()
+                + lora_std as usize
+                + fsk as usize,
+        ))?;
+        for (key, value) in &self.other {
+            map.serialize_entry(key, value)?;
         }
-        if let Some(frequency) = self.chan_multiSF_4.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        for (index, radio) in self.radios.iter().enumerate() {
+            map.serialize_entry(&format!("radio_{}", index), radio)?;
         }
-        if let Some(frequency) = self.chan_multiSF_5.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        for (index, channel) in self.multi_sf.iter().enumerate() {
+            map.serialize_entry(&format!("chan_multiSF_{}", index), channel)?;
         }
-        if let Some(frequency) = self.chan_multiSF_6.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        if lora_std {
+            map.serialize_entry("chan_Lora_std", &self.lora_std)?;
         }
-        if let Some(frequency) = self.chan_multiSF_7.frequency(&self.radio_0, &self.radio_1) {
-            frequencies.push(frequency);
+        if fsk {
+            map.serialize_entry("chan_FSK", &self.fsk)?;
         }
+        map.end()
+    }
+}
 
-        // iterate through all frequencies and confirm that they are between
-        // tx_freq_min and tx_freq_max
-        let mut valid_tx = true;
-        if let (Some(lb), Some(ub)) = (self.radio_0.tx_freq_min, self.radio_0.tx_freq_max) {
-            for frequency in frequencies {
-                if frequency > ub || frequency < lb {
-                    valid_tx = false;
-                }
+impl<'de> Deserialize<'de> for Sx130xConfData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(Sx130xConfDataVisitor)
+    }
+}
+
+struct Sx130xConfDataVisitor;
+
+impl<'de> Visitor<'de> for Sx130xConfDataVisitor {
+    type Value = Sx130xConfData;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an SX130x concentrator configuration")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut radios = BTreeMap::new();
+        let mut multi_sf = BTreeMap::new();
+        let mut lora_std = None;
+        let mut fsk = None;
+        let mut other = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if let Some(index) = indexed_key(&key, "radio_") {
+                radios.insert(index, map.next_value()?);
+            } else if let Some(index) = indexed_key(&key, "chan_multiSF_") {
+                multi_sf.insert(index, map.next_value()?);
+            } else if key == "chan_Lora_std" {
+                lora_std = Some(map.next_value()?);
+            } else if key == "chan_FSK" {
+                fsk = Some(map.next_value()?);
+            } else {
+                let value = map.next_value()?;
+                other.insert(key, value);
             }
-        } else {
-            panic!("No tx freq max and min for radio_0!")
         }
+        if radios.is_empty() {
+            return Err(de::Error::missing_field("radio_0"));
+        }
+        Ok(Sx130xConfData {
+            radios: contiguous(radios, "radio_")?,
+            multi_sf: contiguous(multi_sf, "chan_multiSF_")?,
+            lora_std_listed: lora_std.is_some(),
+            fsk_listed: fsk.is_some(),
+            lora_std: lora_std.unwrap_or_default(),
+            fsk: fsk.unwrap_or_default(),
+            other,
+        })
+    }
+}
 
-        // prepare the summary to be printed
-        let mut summary = String::new();
-        summary.push_str("1        ");
-        summary.push_str(&self.chan_multiSF_0.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n2        ");
-        summary.push_str(&self.chan_multiSF_1.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n3        ");
-        summary.push_str(&self.chan_multiSF_2.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n4        ");
-        summary.push_str(&self.chan_multiSF_3.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n5        ");
-        summary.push_str(&self.chan_multiSF_4.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n6        ");
-        summary.push_str(&self.chan_multiSF_5.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n7        ");
-        summary.push_str(&self.chan_multiSF_6.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\n8        ");
-        summary.push_str(&self.chan_multiSF_7.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\nFat LoRa ");
-        summary.push_str(&self.chan_Lora_std.summary(&self.radio_0, &self.radio_1));
-        summary.push_str("\nFSK      ");
-        summary.push_str(&self.chan_FSK.summary(&self.radio_0, &self.radio_1));
-        if !valid_tx {
-            summary.push_str("\nWARNING: Cannot transmit on all uplink frequencies for POC!");
+// parses the N out of eg: "radio_N"
+fn indexed_key(key: &str, prefix: &str) -> Option<usize> {
+    key.strip_prefix(prefix)?.parse().ok()
+}
+
+// radios are referred to by their index, so a gap would silently shift them
+fn contiguous<T, E: de::Error>(entries: BTreeMap<usize, T>, prefix: &str) -> Result<Vec<T>, E> {
+    let mut list = Vec::with_capacity(entries.len());
+    for (expected, (index, entry)) in entries.into_iter().enumerate() {
+        if index != expected {
+            return Err(E::custom(format!("missing {}{}", prefix, expected)));
         }
-        summary
+        list.push(entry);
     }
+    Ok(list)
 }
 
+/// An RF chain of the concentrator
 #[derive(Deserialize, Serialize, Debug)]
-struct Radio {
-    freq: isize,
-    tx_freq_min: Option<isize>,
-    tx_freq_max: Option<isize>,
+pub struct Radio {
+    /// Centre frequency in Hz. Basic Station station.conf leaves it to the
+    /// LNS router_config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freq: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_freq_min: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_freq_max: Option<isize>,
+    /// Everything else (eg: "type", "rssi_offset", "tx_enable"...)
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
 
+/// A multi-SF LoRa channel
 #[derive(Deserialize, Serialize, Debug)]
-struct Channel {
-    enable: bool,
+pub struct Channel {
+    pub enable: bool,
     #[serde(flatten)]
-    config: Option<ChannelEnabled>,
+    pub config: Option<ChannelEnabled>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct ChannelEnabled {
-    r#if: isize,
-    radio: usize,
+pub struct ChannelEnabled {
+    /// Offset from the radio centre frequency in Hz
+    pub r#if: isize,
+    /// Index into [`Sx130xConfData::radios`]
+    pub radio: usize,
 }
 
 impl Channel {
-    fn frequency(&self, radio_0: &Radio, radio_1: &Radio) -> Option<isize> {
+    /// Radio frequency + IF in Hz, or None when disabled or when the radio
+    /// has no frequency
+    pub fn frequency(&self, radios: &[Radio]) -> error::Result<Option<isize>> {
         if !self.enable {
-            return None;
+            return Ok(None);
         }
-        let &ChannelEnabled { r#if, radio } = self
-            .config
-            .as_ref()
-            .expect("LoRa Channel enabled but no 'radio' and/or no 'if'");
-        Some(match radio {
-            0 => radio_0.freq + r#if,
-            1 => radio_1.freq + r#if,
-            _ => panic!("invalid radio!"),
-        })
-    }
-
-    fn summary(&self, radio_0: &Radio, radio_1: &Radio) -> String {
-        if let Some(frequency) = self.frequency(radio_0, radio_1) {
-            format!("{} MHz", frequency as f64 / 1_000_000.0)
-        } else {
-            "Disabled".to_string()
+        match &self.config {
+            Some(config) => radio_frequency("chan_multiSF", config.radio, config.r#if, radios),
+            None => Err(not_placed("chan_multiSF", "'radio' and/or no 'if'")),
         }
     }
 }
 
 impl LoraStd {
-    fn frequency(&self, radio_0: &Radio, radio_1: &Radio) -> Option<isize> {
-        match self.enable {
-            true => {
-                if let Some(config) = &self.config {
-                    Some(match config.radio {
-                        0 => radio_0.freq + config.r#if,
-                        1 => radio_1.freq + config.r#if,
-                        _ => panic!("invalid radio!"),
-                    })
-                } else {
-                    panic!("LoraStd enabled but no 'radio' and/or no 'if'")
-                }
-            }
-            false => None,
+    /// Radio frequency + IF in Hz, or None when disabled or when the radio
+    /// has no frequency
+    pub fn frequency(&self, radios: &[Radio]) -> error::Result<Option<isize>> {
+        if !self.enable {
+            return Ok(None);
+        }
+        match &self.config {
+            Some(config) => radio_frequency("chan_Lora_std", config.radio, config.r#if, radios),
+            None => Err(not_placed(
+                "chan_Lora_std",
+                "'radio', 'if' and/or 'bandwidth'",
+            )),
         }
     }
 
-    fn bandwidth(&self) -> Option<usize> {
-        match self.enable {
-            true => {
-                if let Some(config) = &self.config {
-                    Some(config.bandwidth)
-                } else {
-                    panic!("LoraStd enabled but no 'bandwidth'")
-                }
-            }
-            false => None,
+    /// Bandwidth in Hz, or None when disabled
+    pub fn bandwidth(&self) -> error::Result<Option<usize>> {
+        if !self.enable {
+            return Ok(None);
+        }
+        match &self.config {
+            Some(config) => Ok(Some(config.bandwidth)),
+            None => Err(not_placed(
+                "chan_Lora_std",
+                "'radio', 'if' and/or 'bandwidth'",
+            )),
         }
     }
+}
 
-    fn summary(&self, radio_0: &Radio, radio_1: &Radio) -> String {
-        if let (Some(frequency), Some(bandwidth)) =
-            (self.frequency(radio_0, radio_1), self.bandwidth())
-        {
-            format!(
-                "{} MHz, BW {} KHz",
-                frequency as f64 / 1_000_000.0,
-                bandwidth as f64 / 1_000.0
-            )
-        } else {
-            "Disabled".to_string()
-        }
+// frequency of an enabled channel
+fn radio_frequency(
+    channel: &str,
+    radio: usize,
+    r#if: isize,
+    radios: &[Radio],
+) -> error::Result<Option<isize>> {
+    match radios.get(radio) {
+        Some(radio) => Ok(radio.freq.map(|freq| freq + r#if)),
+        None => Err(error::Error::InvalidChannel(format!(
+            "{} is on radio_{} which does not exist",
+            channel, radio
+        ))),
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct LoraStd {
-    enable: bool,
+// an enabled channel which failed to parse as "ChannelEnabled",
+// "LoraStdEnabled" or "FskEnabled"
+fn not_placed(channel: &str, fields: &str) -> error::Error {
+    error::Error::InvalidChannel(format!("{} enabled but no {}", channel, fields))
+}
+
+/// The single-SF LoRa channel, usually at 250 or 500 kHz (the "fat" channel)
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct LoraStd {
+    pub enable: bool,
     #[serde(flatten)]
-    config: Option<LoraStdEnabled>,
+    pub config: Option<LoraStdEnabled>,
+    /// Everything else (eg: "spread_factor")
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-struct LoraStdEnabled {
-    bandwidth: usize,
-    r#if: isize,
-    radio: usize,
+pub struct LoraStdEnabled {
+    /// Channel bandwidth in Hz
+    pub bandwidth: usize,
+    /// Offset from the radio centre frequency in Hz
+    pub r#if: isize,
+    /// Index into [`Sx130xConfData::radios`]
+    pub radio: usize,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct ChannelFsk {
-    enable: bool,
+/// The FSK channel
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct ChannelFsk {
+    pub enable: bool,
     #[serde(flatten)]
-    config: Option<LoraStdEnabled>,
+    pub config: Option<FskEnabled>,
+    /// Everything else (eg: "datarate")
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct FskEnabled {
+    /// Channel bandwidth in Hz. Often left out (eg: TTN router_config),
+    /// the forwarder then derives it from the datarate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<usize>,
+    /// Offset from the radio centre frequency in Hz
+    pub r#if: isize,
+    /// Index into [`Sx130xConfData::radios`]
+    pub radio: usize,
 }
 
 impl ChannelFsk {
-    fn frequency(&self, radio_0: &Radio, radio_1: &Radio) -> Option<isize> {
-        match self.enable {
-            true => {
-                if let Some(config) = &self.config {
-                    Some(match config.radio {
-                        0 => radio_0.freq + config.r#if,
-                        1 => radio_1.freq + config.r#if,
-                        _ => panic!("invalid radio!"),
-                    })
-                } else {
-                    panic!("LoraStd enabled but no 'radio' and/or no 'if'")
-                }
-            }
-            false => None,
+    /// Radio frequency + IF in Hz, or None when disabled or when the radio
+    /// has no frequency
+    pub fn frequency(&self, radios: &[Radio]) -> error::Result<Option<isize>> {
+        if !self.enable {
+            return Ok(None);
         }
-    }
-
-    fn bandwidth(&self) -> Option<usize> {
-        match self.enable {
-            true => {
-                if let Some(config) = &self.config {
-                    Some(config.bandwidth)
-                } else {
-                    panic!("ChannelFSK enabled but no 'bandwidth'")
-                }
-            }
-            false => None,
+        match &self.config {
+            Some(config) => radio_frequency("chan_FSK", config.radio, config.r#if, radios),
+            None => Err(not_placed("chan_FSK", "'radio' and/or no 'if'")),
         }
     }
 
-    fn summary(&self, radio_0: &Radio, radio_1: &Radio) -> String {
-        if let (Some(frequency), Some(bandwidth)) =
-            (self.frequency(radio_0, radio_1), self.bandwidth())
-        {
-            format!(
-                "{} MHz, BW {} KHz",
-                frequency as f64 / 1_000_000.0,
-                bandwidth as f64 / 1_000.0
-            )
-        } else {
-            "Disabled".to_string()
+    /// Bandwidth in Hz, or None when disabled or not given
+    pub fn bandwidth(&self) -> error::Result<Option<usize>> {
+        if !self.enable {
+            return Ok(None);
+        }
+        match &self.config {
+            Some(config) => Ok(config.bandwidth),
+            None => Err(not_placed("chan_FSK", "'radio' and/or no 'if'")),
         }
     }
 }
@@ -0,0 +1,36 @@
+//! Parsing and validation of SX1301/SX1302 concentrator configurations.
+//!
+//! Semtech packet forwarder `global_conf.json` files, LoRa Basics Station
+//! `station.conf` files and `router_config` messages are read with
+//! [`Config::from_reader`] or [`str::parse`]. ChirpStack Concentratord TOML
+//! files are read with [`ConcentratordConf`]. All of them end up in the same
+//! [`Sx130xConfData`] model, which can be checked against a
+//! [`regions::Region`] with [`Config::validate`] or turned into a
+//! [`ChannelPlan`] table.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use sx13xx_conf::{Config, Region};
+//!
+//! let config = Config::from_reader(File::open("global_conf.json")?)?;
+//! for issue in config.validate(&Region::US915)? {
+//!     println!("{}", issue);
+//! }
+//! # Ok::<(), sx13xx_conf::Error>(())
+//! ```
+
+pub mod channel_plan;
+pub mod concentratord;
+pub mod error;
+pub mod global_conf;
+pub mod validation;
+
+pub use channel_plan::{ChannelKind, ChannelPlan, PlannedChannel};
+pub use concentratord::ConcentratordConf;
+pub use error::{Error, Result};
+pub use global_conf::{
+    decomment, Channel, ChannelEnabled, ChannelFsk, Config, FskEnabled, LoraStd, LoraStdEnabled,
+    Radio, Sx130xConfData, UpChannel,
+};
+pub use regions::Region;
+pub use validation::{validate, Issue};
@@ -2,17 +2,24 @@ use std::fs::File;
 use std::path::Path;
 use structopt::StructOpt;
 
-use regions::Region;
-
-mod global_conf;
-use global_conf::*;
+use sx13xx_conf::{ConcentratordConf, Config, Region};
 
 #[derive(Debug, StructOpt)]
 /// Tests the frequency configuration of a SX1301
 /// or SX1302 configuration file (global_conf.json)
+/// or of a LoRa Basics Station configuration
+/// (station.conf or an LNS router_config message)
+/// or of a ChirpStack Concentratord TOML file
 pub struct Opt {
     /// Path to global_conf.json under test. SX1301
-    /// and SX1302 configuration files are acceptable
+    /// and SX1302 configuration files are acceptable,
+    /// as are Basic Station station.conf files and
+    /// router_config messages saved as JSON.
+    /// Files ending in .toml are read as ChirpStack
+    /// Concentratord configuration.
+    /// Multi-concentrator files, where the
+    /// concentrator section is an array, are checked
+    /// as a whole.
     /// Comments (eg: "//" or "/* */ and variables
     /// (eg: ${VAR}) are stripped out before parsing
     #[structopt(name = "path_to_conf", required = true)]
@@ -23,6 +30,34 @@ pub struct Opt {
     /// IN865, RU864
     #[structopt(required = true)]
     region: Region,
+    /// Output format of the channel plan: text, json,
+    /// csv or markdown. Validation results go to stderr
+    /// for all formats but text. Exits with 1 when the
+    /// configuration does not match the region
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+}
+
+#[derive(Debug)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+    Markdown,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -34,26 +69,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     if path.is_file() {
-        let file = File::open(&path)?;
-        let config = Config::from_file(file)?;
-        println!("{}", config.summary());
-
-        let channels = opts.region.get_uplink_frequencies();
-
-        for (index, channel) in channels.iter().enumerate() {
-            if let Some(config_frequency) = config.frequency(index) {
-                if &(config_frequency as usize) != channel {
-                    println!(
-                        "Channel {} mismatch! Expected {}, but got {}",
-                        index, channel, config_frequency
-                    );
+        let file = File::open(path)?;
+        let config = if path.extension().is_some_and(|ext| ext == "toml") {
+            ConcentratordConf::from_reader(file)?
+        } else {
+            Config::from_reader(file)?
+        };
+        let issues = config.validate(&opts.region)?;
+        let plan = config.channel_plan()?;
+        match opts.format {
+            OutputFormat::Text => {
+                println!("{}", config.summary()?);
+                for issue in &issues {
+                    println!("{}", issue);
                 }
-            } else {
-                println!(
-                    "Channel {} mismatch! Expected {}, but channel not configured",
-                    index, channel
-                );
             }
+            OutputFormat::Json => println!("{}", plan.to_json()?),
+            OutputFormat::Csv => println!("{}", plan.to_csv()),
+            OutputFormat::Markdown => println!("{}", plan.to_markdown()),
+        }
+        if !matches!(opts.format, OutputFormat::Text) {
+            for issue in &issues {
+                eprintln!("{}", issue);
+            }
+        }
+        if !issues.is_empty() {
+            std::process::exit(1);
         }
     }
     Ok(())
@@ -0,0 +1,179 @@
+use super::error::Result;
+use super::global_conf::Config;
+use regions::Region;
+use std::fmt;
+
+/// Problems found when checking a configuration against a region
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// A region uplink channel is not served by any concentrator
+    MissingChannel { index: usize, expected: usize },
+    /// A multi-SF channel is outside of the region's channel plan
+    UnexpectedChannel {
+        concentrator: usize,
+        frequency: isize,
+    },
+    /// A concentrator only covers part of a sub-band
+    PartialSubBand {
+        concentrator: usize,
+        sub_band: usize,
+        missing: Vec<usize>,
+    },
+    /// Two concentrators are tuned to the same sub-band
+    DuplicateSubBand {
+        concentrator: usize,
+        sub_band: usize,
+    },
+    /// The fat channel does not belong to the concentrator's sub-band
+    FatChannelMismatch {
+        concentrator: usize,
+        expected: usize,
+        got: isize,
+    },
+}
+
+/// Checks the channels of all concentrators combined against the region.
+/// For fixed channel plan regions (US915, AU915) each concentrator is also
+/// expected to cover exactly one sub-band, so that a 16 channel gateway
+/// listens on a pair of sub-bands
+pub fn validate(config: &Config, region: &Region) -> Result<Vec<Issue>> {
+    let mut issues = Vec::new();
+
+    let frequencies = config.frequencies()?;
+    for (index, expected) in region.get_uplink_frequencies().iter().enumerate() {
+        if !frequencies.contains(&(*expected as isize)) {
+            issues.push(Issue::MissingChannel {
+                index,
+                expected: *expected,
+            });
+        }
+    }
+
+    let sub_bands = region.get_sub_bands();
+    let mut covered_sub_bands = Vec::new();
+    for (index, concentrator) in config.concentrators().iter().enumerate() {
+        let multi_sf = concentrator.multi_sf_frequencies()?;
+        if multi_sf.is_empty() {
+            continue;
+        }
+
+        if sub_bands.is_empty() {
+            let plan = region.get_uplink_frequencies();
+            for frequency in multi_sf {
+                if !plan.contains(&(frequency as usize)) {
+                    issues.push(Issue::UnexpectedChannel {
+                        concentrator: index,
+                        frequency,
+                    });
+                }
+            }
+            continue;
+        }
+
+        // the sub-band this concentrator is closest to
+        let sub_band = sub_bands
+            .iter()
+            .max_by_key(|sub_band| {
+                let overlap = multi_sf
+                    .iter()
+                    .filter(|frequency| sub_band.channels.contains(&(**frequency as usize)))
+                    .count();
+                // prefer the lowest sub-band on a tie
+                (overlap, std::cmp::Reverse(sub_band.number))
+            })
+            .expect("fixed channel plan regions have sub-bands");
+
+        for frequency in &multi_sf {
+            if !sub_band.channels.contains(&(*frequency as usize)) {
+                issues.push(Issue::UnexpectedChannel {
+                    concentrator: index,
+                    frequency: *frequency,
+                });
+            }
+        }
+        let missing: Vec<usize> = sub_band
+            .channels
+            .iter()
+            .filter(|channel| !multi_sf.contains(&(**channel as isize)))
+            .cloned()
+            .collect();
+        if missing.len() == sub_band.channels.len() {
+            // nothing in common with any sub-band, already reported above
+            continue;
+        }
+        if !missing.is_empty() {
+            issues.push(Issue::PartialSubBand {
+                concentrator: index,
+                sub_band: sub_band.number,
+                missing,
+            });
+        }
+        if covered_sub_bands.contains(&sub_band.number) {
+            issues.push(Issue::DuplicateSubBand {
+                concentrator: index,
+                sub_band: sub_band.number,
+            });
+        }
+        covered_sub_bands.push(sub_band.number);
+
+        if let Some(fat_channel) = concentrator.lora_std_frequency()? {
+            if fat_channel as usize != sub_band.fat_channel {
+                issues.push(Issue::FatChannelMismatch {
+                    concentrator: index,
+                    expected: sub_band.fat_channel,
+                    got: fat_channel,
+                });
+            }
+        }
+    }
+    Ok(issues)
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::MissingChannel { index, expected } => write!(
+                f,
+                "Channel {} mismatch! Expected {}, but channel not configured",
+                index, expected
+            ),
+            Issue::UnexpectedChannel {
+                concentrator,
+                frequency,
+            } => write!(
+                f,
+                "Concentrator {}: {} MHz is not an uplink channel of the region",
+                concentrator,
+                *frequency as f64 / 1_000_000.0
+            ),
+            Issue::PartialSubBand {
+                concentrator,
+                sub_band,
+                missing,
+            } => write!(
+                f,
+                "Concentrator {}: sub-band {} is missing {:?}",
+                concentrator, sub_band, missing
+            ),
+            Issue::DuplicateSubBand {
+                concentrator,
+                sub_band,
+            } => write!(
+                f,
+                "Concentrator {}: sub-band {} is already covered by another concentrator",
+                concentrator, sub_band
+            ),
+            Issue::FatChannelMismatch {
+                concentrator,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Concentrator {}: fat channel expected at {} MHz, but got {} MHz",
+                concentrator,
+                *expected as f64 / 1_000_000.0,
+                *got as f64 / 1_000_000.0
+            ),
+        }
+    }
+}
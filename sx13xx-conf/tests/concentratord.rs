@@ -0,0 +1,105 @@
+use sx13xx_conf::{ConcentratordConf, Error, Sx130xConfData};
+
+fn convert(toml: &str) -> Result<Sx130xConfData, Error> {
+    toml.parse::<ConcentratordConf>()?.into_sx130x_conf()
+}
+
+fn radio_of(conf: &Sx130xConfData, channel: usize) -> usize {
+    conf.multi_sf[channel].config.as_ref().unwrap().radio
+}
+
+const EU868: &str = r#"
+[gateway.concentrator]
+multi_sf_channels = [
+    867100000, 867300000, 867500000, 867700000,
+    867900000, 868100000, 868300000, 868500000,
+]
+
+[gateway.concentrator.lora_std]
+frequency = 868300000
+bandwidth = 250000
+"#;
+
+#[test]
+fn splits_channels_evenly_between_radios() {
+    let conf = convert(EU868).unwrap();
+
+    let centers: Vec<Option<isize>> = conf.radios.iter().map(|radio| radio.freq).collect();
+    assert_eq!(centers, [Some(867_400_000), Some(868_200_000)]);
+    let radios: Vec<usize> = (0..8).map(|channel| radio_of(&conf, channel)).collect();
+    assert_eq!(radios, [0, 0, 0, 0, 1, 1, 1, 1]);
+    assert_eq!(
+        conf.multi_sf_frequencies().unwrap(),
+        [
+            867_100_000,
+            867_300_000,
+            867_500_000,
+            867_700_000,
+            867_900_000,
+            868_100_000,
+            868_300_000,
+            868_500_000
+        ]
+    );
+    assert_eq!(conf.lora_std_frequency().unwrap(), Some(868_300_000));
+    assert_eq!(conf.lora_std.config.as_ref().unwrap().radio, 1);
+}
+
+#[test]
+fn leaves_an_unused_radio_disabled() {
+    let conf = convert(
+        r#"
+        [gateway.concentrator]
+        multi_sf_channels = [868100000]
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(radio_of(&conf, 0), 0);
+    assert!(conf.multi_sf[1..].iter().all(|channel| !channel.enable));
+    assert_eq!(conf.radios[0].freq, Some(868_100_000));
+    assert_eq!(conf.radios[0].other["enable"], true);
+    assert_eq!(conf.radios[1].freq, None);
+    assert_eq!(conf.radios[1].other["enable"], false);
+
+    let written = serde_json::to_value(&conf).unwrap();
+    assert!(written["radio_1"].get("freq").is_none());
+    assert_eq!(written["radio_1"]["enable"], false);
+}
+
+#[test]
+fn rejects_more_than_eight_channels() {
+    let error = convert(
+        r#"
+        [gateway.concentrator]
+        multi_sf_channels = [
+            867100000, 867300000, 867500000, 867700000, 867900000,
+            868100000, 868300000, 868500000, 868700000,
+        ]
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(matches!(error, Error::InvalidChannelPlan(_)));
+    assert!(error.to_string().contains("9 multi-SF channels"));
+}
+
+#[test]
+fn rejects_channels_out_of_reach_of_both_radios() {
+    let error = convert(
+        r#"
+        [gateway.concentrator]
+        multi_sf_channels = [863100000, 866100000, 869100000]
+        "#,
+    )
+    .unwrap_err();
+
+    assert!(matches!(error, Error::InvalidChannelPlan(_)));
+}
+
+#[test]
+fn summary_does_not_ask_for_a_tx_range() {
+    let config = ConcentratordConf::from_reader(EU868.as_bytes()).unwrap();
+    let summary = config.summary().unwrap();
+    assert!(!summary.contains("tx_freq_min"), "{}", summary);
+}
@@ -0,0 +1,127 @@
+use serde_json::{json, Value};
+use std::fs::File;
+use sx13xx_conf::{ChannelKind, Config};
+
+// one multi-SF channel enabled and one disabled, a 250 kHz fat channel and
+// no FSK channel
+const PLAN: &str = r#"{
+    "SX130x_conf": {
+        "radio_0": { "enable": true, "type": "SX1257", "freq": 868500000 },
+        "radio_1": { "enable": true, "type": "SX1257", "freq": 867500000 },
+        "chan_multiSF_0": { "enable": true, "radio": 0, "if": -400000 },
+        "chan_multiSF_1": { "enable": false, "radio": 1, "if": 0 },
+        "chan_Lora_std": {
+            "enable": true, "radio": 0, "if": -200000,
+            "bandwidth": 250000, "spread_factor": 7
+        },
+        "chan_FSK": { "enable": false }
+    }
+}"#;
+
+fn fixture(name: &str) -> Config {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    Config::from_reader(File::open(path).unwrap()).unwrap()
+}
+
+#[test]
+fn router_config_fsk_without_bandwidth() {
+    let config = fixture("router_config.json");
+    let concentrator = &config.concentrators()[0];
+    assert_eq!(
+        concentrator.fsk.frequency(&concentrator.radios).unwrap(),
+        Some(868_800_000)
+    );
+    assert_eq!(concentrator.fsk.bandwidth().unwrap(), None);
+    assert_eq!(
+        concentrator.lora_std_frequency().unwrap(),
+        Some(868_300_000)
+    );
+    assert_eq!(config.upchannels().len(), 8);
+
+    let plan = config.channel_plan().unwrap();
+    let fsk = plan
+        .channels
+        .iter()
+        .find(|channel| channel.kind == ChannelKind::Fsk)
+        .unwrap();
+    assert!(fsk.enabled);
+    assert_eq!(fsk.freq, Some(868_800_000));
+    assert_eq!(fsk.bandwidth, None);
+    let summary = config.summary().unwrap();
+    assert!(summary.contains("FSK      868.8 MHz"));
+    assert!(!summary.contains("WARNING"));
+}
+
+#[test]
+fn station_conf_without_channels() {
+    let config = fixture("station.conf");
+    let concentrator = &config.concentrators()[0];
+    assert_eq!(concentrator.radios.len(), 2);
+    assert!(concentrator.radios.iter().all(|radio| radio.freq.is_none()));
+    assert!(concentrator.multi_sf.is_empty());
+    assert!(!concentrator.lora_std.enable);
+    assert!(!concentrator.fsk.enable);
+    assert!(config.frequencies().unwrap().is_empty());
+    assert_eq!(concentrator.other["lorawan_public"], true);
+    assert_eq!(concentrator.other["clksrc"], 1);
+    assert!(config
+        .channel_plan()
+        .unwrap()
+        .channels
+        .iter()
+        .all(|channel| !channel.enabled));
+}
+
+#[test]
+fn channel_plan_as_json() {
+    let plan = PLAN.parse::<Config>().unwrap().channel_plan().unwrap();
+    let json: Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+    assert_eq!(
+        json,
+        json!({ "channels": [
+            {
+                "concentrator": 0, "slot": 0, "kind": "multiSF", "radio": 0,
+                "if": -400000, "freq": 868100000, "bandwidth": 125000, "enabled": true
+            },
+            {
+                "concentrator": 0, "slot": 1, "kind": "multiSF", "radio": 1,
+                "if": 0, "freq": null, "bandwidth": null, "enabled": false
+            },
+            {
+                "concentrator": 0, "slot": 0, "kind": "std", "radio": 0,
+                "if": -200000, "freq": 868300000, "bandwidth": 250000, "enabled": true
+            },
+            {
+                "concentrator": 0, "slot": 0, "kind": "FSK", "radio": null,
+                "if": null, "freq": null, "bandwidth": null, "enabled": false
+            }
+        ]})
+    );
+}
+
+#[test]
+fn channel_plan_as_csv() {
+    let plan = PLAN.parse::<Config>().unwrap().channel_plan().unwrap();
+    assert_eq!(
+        plan.to_csv(),
+        "concentrator,slot,kind,radio,if,freq,bandwidth,enabled\n\
+         0,0,multiSF,0,-400000,868100000,125000,true\n\
+         0,1,multiSF,1,0,,,false\n\
+         0,0,std,0,-200000,868300000,250000,true\n\
+         0,0,FSK,,,,,false"
+    );
+}
+
+#[test]
+fn channel_plan_as_markdown() {
+    let plan = PLAN.parse::<Config>().unwrap().channel_plan().unwrap();
+    assert_eq!(
+        plan.to_markdown(),
+        "| Concentrator | Slot | Kind | Radio | IF (Hz) | Frequency (MHz) | Bandwidth (kHz) | Enabled |\n\
+         |---|---|---|---|---|---|---|---|\n\
+         | 0 | 0 | multiSF | 0 | -400000 | 868.1 | 125 | yes |\n\
+         | 0 | 1 | multiSF | 1 | 0 |  |  | no |\n\
+         | 0 | 0 | std | 0 | -200000 | 868.3 | 250 | yes |\n\
+         | 0 | 0 | FSK |  |  |  |  | no |"
+    );
+}
@@ -0,0 +1,142 @@
+use serde_json::Value;
+use std::fs;
+use sx13xx_conf::{decomment, Config, Error};
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    fs::read_to_string(path).unwrap()
+}
+
+fn write(config: &Config) -> String {
+    let mut written = Vec::new();
+    config.to_writer(&mut written).unwrap();
+    String::from_utf8(written).unwrap()
+}
+
+// from_str -> to_writer -> from_str gives back the same file, and every key
+// of the original survives
+fn round_trip(name: &str) {
+    let contents = fixture(name);
+    let written = write(&contents.parse().unwrap());
+    let reread: Config = written.parse().unwrap();
+    assert_eq!(write(&reread), written);
+
+    let original: Value = serde_json::from_str(&decomment(&contents).unwrap()).unwrap();
+    let written: Value = serde_json::from_str(&written).unwrap();
+    assert_contains(&written, &original, name);
+}
+
+fn assert_contains(written: &Value, original: &Value, path: &str) {
+    match original {
+        Value::Object(original) => {
+            for (key, value) in original {
+                let path = format!("{}.{}", path, key);
+                let written = written
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} dropped", path));
+                assert_contains(written, value, &path);
+            }
+        }
+        _ => assert_eq!(written, original, "{}", path),
+    }
+}
+
+#[test]
+fn global_conf_round_trip() {
+    round_trip("global_conf.json");
+
+    let written = write(&fixture("global_conf.json").parse().unwrap());
+    for key in &[
+        "lorawan_public",
+        "clksrc",
+        "antenna_gain",
+        "tx_lut_0",
+        "tx_lut_1",
+    ] {
+        assert!(written.contains(key), "{} dropped", key);
+    }
+}
+
+#[test]
+fn router_config_round_trip() {
+    round_trip("router_config.json");
+}
+
+#[test]
+fn station_conf_round_trip() {
+    round_trip("station.conf");
+}
+
+#[test]
+fn missing_channels_are_not_written() {
+    let written = write(&fixture("station.conf").parse().unwrap());
+    assert!(!written.contains("chan_Lora_std"));
+    assert!(!written.contains("chan_FSK"));
+
+    // a disabled channel stays where it was
+    let written = write(
+        &r#"{"SX1301_conf": {
+            "radio_0": {"freq": 867500000},
+            "chan_FSK": {"enable": false}
+        }}"#
+        .parse()
+        .unwrap(),
+    );
+    assert!(!written.contains("chan_Lora_std"));
+    assert!(written.contains("chan_FSK"));
+}
+
+#[test]
+fn url_is_not_a_comment() {
+    let decommented = decomment(r#"{"tc_uri": "wss://lns.example.com:8887", "a": "\"//\""}"#);
+    assert_eq!(
+        decommented.unwrap(),
+        r#"{"tc_uri": "wss://lns.example.com:8887", "a": "\"//\""}"#
+    );
+}
+
+#[test]
+fn variable_is_emptied() {
+    let decommented = decomment("{\"gateway_ID\": ${GATEWAY_EUI} // from the env\n}");
+    assert_eq!(decommented.unwrap(), "{\"gateway_ID\": \"\" }");
+}
+
+#[test]
+fn unclosed_comment_is_an_error() {
+    assert!(matches!(
+        decomment("{\"clksrc\": 1 /* radio_1"),
+        Err(Error::Decomment(_))
+    ));
+    assert!(matches!(
+        "{\"SX1301_conf\": ${CONF".parse::<Config>(),
+        Err(Error::Decomment(_))
+    ));
+}
+
+#[test]
+fn channel_on_a_missing_radio_is_an_error() {
+    let config: Config = r#"{"SX1301_conf": {
+        "radio_0": {"freq": 867500000},
+        "chan_multiSF_0": {"enable": true, "radio": 1, "if": 0},
+        "chan_Lora_std": {"enable": true, "radio": 0, "if": 0}
+    }}"#
+    .parse()
+    .unwrap();
+    let concentrator = &config.concentrators()[0];
+    assert!(matches!(
+        concentrator.multi_sf[0].frequency(&concentrator.radios),
+        Err(Error::InvalidChannel(_))
+    ));
+    // no bandwidth
+    assert!(matches!(
+        concentrator.lora_std.frequency(&concentrator.radios),
+        Err(Error::InvalidChannel(_))
+    ));
+    assert!(matches!(
+        concentrator.lora_std.bandwidth(),
+        Err(Error::InvalidChannel(_))
+    ));
+    assert!(config.channel_plan().is_err());
+    assert!(config.summary().is_err());
+    assert!(config.validate(&sx13xx_conf::Region::EU868).is_err());
+}
@@ -0,0 +1,241 @@
+use serde_json::{json, Map, Value};
+use sx13xx_conf::{validate, Config, Issue, Region};
+
+// the multi-SF channels and the fat channel of a concentrator, in Hz
+type Concentrator = (Vec<usize>, Option<usize>);
+
+fn sub_band(region: &Region, number: usize) -> Vec<usize> {
+    region.get_sub_bands()[number - 1].channels.to_vec()
+}
+
+fn fat_channel(region: &Region, number: usize) -> Option<usize> {
+    Some(region.get_sub_bands()[number - 1].fat_channel)
+}
+
+// a global_conf.json with every channel on radio_0, tuned to the lowest one
+fn config(concentrators: &[Concentrator]) -> Config {
+    let concentrators: Vec<Value> = concentrators
+        .iter()
+        .map(|(multi_sf, fat_channel)| {
+            let center = multi_sf.iter().chain(fat_channel).min().unwrap();
+            let mut conf = Map::new();
+            conf.insert(
+                "radio_0".into(),
+                json!({ "enable": true, "type": "SX1257", "freq": center }),
+            );
+            conf.insert(
+                "radio_1".into(),
+                json!({ "enable": false, "type": "SX1257", "freq": center }),
+            );
+            for (index, channel) in multi_sf.iter().enumerate() {
+                conf.insert(
+                    format!("chan_multiSF_{}", index),
+                    json!({ "enable": true, "radio": 0, "if": channel - center }),
+                );
+            }
+            conf.insert(
+                "chan_Lora_std".into(),
+                match fat_channel {
+                    Some(channel) => json!({
+                        "enable": true,
+                        "radio": 0,
+                        "if": channel - center,
+                        "bandwidth": 500000,
+                        "spread_factor": 8
+                    }),
+                    None => json!({ "enable": false }),
+                },
+            );
+            conf.insert("chan_FSK".into(), json!({ "enable": false }));
+            Value::Object(conf)
+        })
+        .collect();
+    let conf = match concentrators.as_slice() {
+        [one] => one.clone(),
+        many => Value::Array(many.to_vec()),
+    };
+    json!({ "SX130x_conf": conf }).to_string().parse().unwrap()
+}
+
+fn missing(region: &Region, indices: std::ops::Range<usize>) -> Vec<Issue> {
+    let plan = region.get_uplink_frequencies();
+    indices
+        .map(|index| Issue::MissingChannel {
+            index,
+            expected: plan[index],
+        })
+        .collect()
+}
+
+fn check(region: &Region, cases: Vec<(&str, Vec<Concentrator>, Vec<Issue>)>) {
+    for (name, concentrators, expected) in cases {
+        let issues = validate(&config(&concentrators), region).unwrap();
+        assert_eq!(issues, expected, "{:?}: {}", region, name);
+    }
+}
+
+#[test]
+fn us915() {
+    let region = Region::US915;
+    let sb1 = sub_band(&region, 1);
+    let sb2 = sub_band(&region, 2);
+    let fat1 = fat_channel(&region, 1);
+    let fat2 = fat_channel(&region, 2);
+
+    let mut seven = sb2.clone();
+    seven.pop();
+    let mut stray = sb2.clone();
+    stray[7] = sb1[0];
+    // half of each sub-band: the lowest one wins
+    let straddling: Vec<usize> = sb1[4..].iter().chain(&sb2[..4]).cloned().collect();
+
+    check(
+        &region,
+        vec![
+            ("one sub-band", vec![(sb2.clone(), fat2)], vec![]),
+            (
+                "two sub-bands",
+                vec![(sb1.clone(), fat1), (sb2.clone(), fat2)],
+                vec![],
+            ),
+            (
+                "the wrong sub-band",
+                vec![(sb1.clone(), fat1)],
+                missing(&region, 0..8),
+            ),
+            (
+                "a sub-band twice",
+                vec![(sb2.clone(), fat2), (sb2.clone(), fat2)],
+                vec![Issue::DuplicateSubBand {
+                    concentrator: 1,
+                    sub_band: 2,
+                }],
+            ),
+            (
+                "seven channels",
+                vec![(seven, fat2)],
+                [
+                    missing(&region, 7..8),
+                    vec![Issue::PartialSubBand {
+                        concentrator: 0,
+                        sub_band: 2,
+                        missing: vec![sb2[7]],
+                    }],
+                ]
+                .concat(),
+            ),
+            (
+                "a channel of another sub-band",
+                vec![(stray, fat2)],
+                [
+                    missing(&region, 7..8),
+                    vec![
+                        Issue::UnexpectedChannel {
+                            concentrator: 0,
+                            frequency: sb1[0] as isize,
+                        },
+                        Issue::PartialSubBand {
+                            concentrator: 0,
+                            sub_band: 2,
+                            missing: vec![sb2[7]],
+                        },
+                    ],
+                ]
+                .concat(),
+            ),
+            (
+                "a tie between sub-bands",
+                vec![(straddling, fat1)],
+                [
+                    missing(&region, 4..8),
+                    sb2[..4]
+                        .iter()
+                        .map(|frequency| Issue::UnexpectedChannel {
+                            concentrator: 0,
+                            frequency: *frequency as isize,
+                        })
+                        .collect(),
+                    vec![Issue::PartialSubBand {
+                        concentrator: 0,
+                        sub_band: 1,
+                        missing: sb1[..4].to_vec(),
+                    }],
+                ]
+                .concat(),
+            ),
+            (
+                "the fat channel of another sub-band",
+                vec![(sb2.clone(), fat1)],
+                vec![Issue::FatChannelMismatch {
+                    concentrator: 0,
+                    expected: fat2.unwrap(),
+                    got: fat1.unwrap() as isize,
+                }],
+            ),
+        ],
+    );
+}
+
+#[test]
+fn au915() {
+    let region = Region::AU915;
+    let sb1 = sub_band(&region, 1);
+    let sb2 = sub_band(&region, 2);
+    let fat1 = fat_channel(&region, 1);
+    let fat2 = fat_channel(&region, 2);
+
+    check(
+        &region,
+        vec![
+            ("one sub-band", vec![(sb2.clone(), fat2)], vec![]),
+            (
+                "two sub-bands",
+                vec![(sb1.clone(), fat1), (sb2.clone(), fat2)],
+                vec![],
+            ),
+            (
+                "no fat channel",
+                vec![(sb2.clone(), None)],
+                missing(&region, 8..9),
+            ),
+            (
+                "a sub-band twice",
+                vec![(sb2.clone(), fat2), (sb2.clone(), fat2)],
+                vec![Issue::DuplicateSubBand {
+                    concentrator: 1,
+                    sub_band: 2,
+                }],
+            ),
+            (
+                "the fat channel on the wrong concentrator",
+                vec![(sb1.clone(), fat2), (sb2.clone(), fat2)],
+                vec![Issue::FatChannelMismatch {
+                    concentrator: 0,
+                    expected: fat1.unwrap(),
+                    got: fat2.unwrap() as isize,
+                }],
+            ),
+        ],
+    );
+}
+
+#[test]
+fn dynamic_plan_regions_check_channels_only() {
+    let region = Region::EU868;
+    let mut plan = region.get_uplink_frequencies()[..8].to_vec();
+    plan[7] = 869_100_000;
+    let issues = validate(&config(&[(plan, Some(868_300_000))]), &region).unwrap();
+    assert_eq!(
+        issues,
+        [
+            Issue::MissingChannel {
+                index: 7,
+                expected: 867_900_000
+            },
+            Issue::UnexpectedChannel {
+                concentrator: 0,
+                frequency: 869_100_000
+            }
+        ]
+    );
+}